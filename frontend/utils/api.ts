@@ -84,7 +84,8 @@ async function post<T = unknown>(path: string, body: any, json?: boolean): Promi
 export interface Problem {
     id: number,
     name: string,
-    type: 'standard' | 'strict' | 'spj' | 'dynamic_ranking'
+    type: 'standard' | 'strict' | 'spj' | 'dynamic_ranking' | 'float' |
+        'token' | 'case_insensitive' | 'unordered_lines' | 'interactive' | 'output_only'
     desc: string,
     cases: number,
     score: number
@@ -98,7 +99,16 @@ export interface User {
 export interface Ranking {
     user: User,
     rank: number,
-    scores: number[]
+    scores: number[],
+    results: (Status | null)[],
+    solved?: number,
+    penalty?: number,
+    problems?: ProblemAttempts[]
+}
+
+export interface ProblemAttempts {
+    attempts: number,
+    solvedAt: number | null
 }
 
 export interface Job {
@@ -110,6 +120,7 @@ export interface Job {
     result: Status,
     score: number,
     cases: JobCase[],
+    subtasks: JobSubtask[],
 }
 
 export interface JobRequest {
@@ -117,13 +128,21 @@ export interface JobRequest {
     problemId: number,
     contestId: number,
     sourceCode: string,
-    language: string
+    language: string,
+    outputs?: string[]
+}
+
+export interface JobSubtask {
+    id: number,
+    result: Status,
+    score: number
 }
 
 export interface JobCase {
     id: number,
     result: Status,
     time: number,
+    wallTime: number,
     memory: number,
     info: string,
 }
@@ -131,7 +150,7 @@ export interface JobCase {
 export type Status = 
     'Waiting' | 'Running' | 'Accepted' | 'Compilation Error' | 'Compilation Success' | 
     'Wrong Answer' | 'Runtime Error' | 'Time Limit Exceeded' | 'Memory Limit Exceeded' | 
-    'System Error' | 'SPJ Error' | 'Skipped';
+    'System Error' | 'SPJ Error' | 'Restricted Function' | 'Partially Correct' | 'Skipped';
 
 export type JobStatus = 'Queueing' | 'Running' | 'Finished' | 'Canceled';
 
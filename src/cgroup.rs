@@ -0,0 +1,89 @@
+use std::fs::{create_dir, read_to_string, remove_dir, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::sleep;
+use std::time::Duration;
+
+use crate::models::*;
+
+const CONTROLLERS: &str = "+memory +pids +cpu";
+const CPU_PERIOD: u64 = 100000;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+pub struct Cgroup {
+    path: PathBuf,
+}
+
+fn read_key(path: &Path, key: &str) -> Option<u64> {
+    let content = read_to_string(path).ok()?;
+    content.lines().find_map(|line| {
+        let (name, value) = line.split_once(' ')?;
+        if name == key { value.trim().parse().ok() } else { None }
+    })
+}
+
+// unlike fs::write, never creates the file, so a non-cgroup path fails loudly
+fn write(path: impl AsRef<Path>, value: impl AsRef<[u8]>) -> io::Result<()> {
+    OpenOptions::new().write(true).open(path)?.write_all(value.as_ref())
+}
+
+fn limit_value(limit: u64) -> String {
+    if limit > 0 { limit.to_string() } else { "max".into() }
+}
+
+impl Cgroup {
+    // prepares the delegated subtree that holds one leaf per run
+    pub fn setup_root(root: &str) -> io::Result<PathBuf> {
+        let root = PathBuf::from(root);
+        let parent = root.parent().unwrap_or(&root);
+        if !parent.join("cgroup.controllers").exists() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "not a cgroup v2 hierarchy"));
+        }
+        let _ = write(parent.join("cgroup.subtree_control"), CONTROLLERS);
+        if !root.exists() {
+            create_dir(&root)?;
+        }
+        write(root.join("cgroup.subtree_control"), CONTROLLERS)?;
+        Ok(root)
+    }
+    pub fn new(root: &Path, case: &Case) -> io::Result<Self> {
+        let cgroup = Self {
+            path: root.join(format!("run-{}-{}", std::process::id(), NEXT_ID.fetch_add(1, Ordering::Relaxed)))
+        };
+        create_dir(&cgroup.path)?;
+        write(cgroup.path.join("memory.max"), limit_value(case.memory_limit))?;
+        let _ = write(cgroup.path.join("memory.swap.max"), "0");
+        write(cgroup.path.join("pids.max"), limit_value(case.process_limit))?;
+        let quota = (case.cpu_limit * CPU_PERIOD as f64) as u64;
+        write(cgroup.path.join("cpu.max"), format!("{} {}", limit_value(quota), CPU_PERIOD))?;
+        Ok(cgroup)
+    }
+    // done by the parent while the child is still waiting to exec
+    pub fn attach(&self, pid: libc::pid_t) -> io::Result<()> {
+        write(self.path.join("cgroup.procs"), pid.to_string())
+    }
+    pub fn oom_killed(&self) -> bool {
+        read_key(&self.path.join("memory.events"), "oom_kill").is_some_and(|count| count > 0)
+    }
+    pub fn cpu_time(&self) -> Option<u64> {
+        read_key(&self.path.join("cpu.stat"), "usage_usec")
+    }
+    pub fn memory_peak(&self) -> Option<u64> {
+        read_to_string(self.path.join("memory.peak")).ok()?.trim().parse().ok()
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        let _ = write(self.path.join("cgroup.kill"), "1");
+        // the kernel refuses to remove a cgroup until its last process is reaped
+        for _ in 0..100 {
+            if remove_dir(&self.path).is_ok() || !self.path.exists() {
+                break;
+            }
+            sleep(Duration::from_millis(10));
+        }
+    }
+}
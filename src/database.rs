@@ -1,15 +1,47 @@
 use std::fs::remove_file;
 
-use redb::TableDefinition;
+use chrono::{DateTime, Utc};
+use redb::{ReadableTable, ReadableTableMetadata, TableDefinition, WriteTransaction};
 use crate::models::*;
 
 pub struct Database {
     inner: redb::Database
 }
 
+// the index a lookup goes through, before its predicate decides on each job found
+pub enum JobIndex {
+    All,
+    User(i32),
+    UserProblem(i32, i32),
+    Problem(i32),
+    Contest(i32),
+    CreatedTime(Option<DateTime<Utc>>, Option<DateTime<Utc>>),
+}
+
 const JOBS: TableDefinition<i32, Vec<u8>> = TableDefinition::new("jobs");
 const CONTESTS: TableDefinition<i32, Vec<u8>> = TableDefinition::new("contests");
 const USERS: TableDefinition<i32, Vec<u8>> = TableDefinition::new("users");
+// index keys end with the job id, and the values are empty
+const JOBS_BY_USER_PROBLEM: TableDefinition<(i32, i32, i32), ()> = TableDefinition::new("jobs_by_user_problem");
+const JOBS_BY_PROBLEM: TableDefinition<(i32, i32), ()> = TableDefinition::new("jobs_by_problem");
+const JOBS_BY_CONTEST: TableDefinition<(i32, i32), ()> = TableDefinition::new("jobs_by_contest");
+const JOBS_BY_TIME: TableDefinition<(i64, i32), ()> = TableDefinition::new("jobs_by_time");
+
+fn index_job(txn: &WriteTransaction, job: &Job) -> Result<()> {
+    txn.open_table(JOBS_BY_USER_PROBLEM)?.insert((job.submission.user_id, job.submission.problem_id, job.id), ())?;
+    txn.open_table(JOBS_BY_PROBLEM)?.insert((job.submission.problem_id, job.id), ())?;
+    txn.open_table(JOBS_BY_CONTEST)?.insert((job.submission.contest_id, job.id), ())?;
+    txn.open_table(JOBS_BY_TIME)?.insert((job.created_time.timestamp_micros(), job.id), ())?;
+    Ok(())
+}
+
+fn unindex_job(txn: &WriteTransaction, job: &Job) -> Result<()> {
+    txn.open_table(JOBS_BY_USER_PROBLEM)?.remove((job.submission.user_id, job.submission.problem_id, job.id))?;
+    txn.open_table(JOBS_BY_PROBLEM)?.remove((job.submission.problem_id, job.id))?;
+    txn.open_table(JOBS_BY_CONTEST)?.remove((job.submission.contest_id, job.id))?;
+    txn.open_table(JOBS_BY_TIME)?.remove((job.created_time.timestamp_micros(), job.id))?;
+    Ok(())
+}
 
 impl Database {
     pub fn new(flush_data: bool) -> Result<Self> {
@@ -19,7 +51,29 @@ impl Database {
         let db = redb::Database::create("storage.redb")?;
         let write_txn = db.begin_write()?;
         {
-            write_txn.open_table(JOBS)?;
+            // every index is opened, so it exists from the start, and a database from before one gets them all built once
+            let jobs = write_txn.open_table(JOBS)?;
+            let lengths = [
+                write_txn.open_table(JOBS_BY_USER_PROBLEM)?.len()?,
+                write_txn.open_table(JOBS_BY_PROBLEM)?.len()?,
+                write_txn.open_table(JOBS_BY_CONTEST)?.len()?,
+                write_txn.open_table(JOBS_BY_TIME)?.len()?,
+            ];
+            let count = jobs.len()?;
+            if lengths.iter().any(|length| *length != count) {
+                let mut all = vec![];
+                for kv in jobs.range::<i32>(..)? {
+                    all.push(serde_json::from_slice::<Job>(&kv?.1.value())?);
+                }
+                drop(jobs);
+                write_txn.delete_table(JOBS_BY_USER_PROBLEM)?;
+                write_txn.delete_table(JOBS_BY_PROBLEM)?;
+                write_txn.delete_table(JOBS_BY_CONTEST)?;
+                write_txn.delete_table(JOBS_BY_TIME)?;
+                for job in &all {
+                    index_job(&write_txn, job)?;
+                }
+            }
             write_txn.open_table(CONTESTS)?;
             let mut users = write_txn.open_table(USERS)?;
             users.insert(0, serde_json::to_vec(&User {
@@ -50,11 +104,45 @@ impl Database {
         let write_txn = self.inner.begin_write()?;
         {
             let mut jobs = write_txn.open_table(JOBS)?;
-            jobs.insert(job.id, serde_json::to_vec(job)?)?;
+            let old = match jobs.insert(job.id, serde_json::to_vec(job)?)? {
+                Some(v) => Some(serde_json::from_slice::<Job>(&v.value())?),
+                None => None
+            };
+            drop(jobs);
+            if let Some(old) = old {
+                unindex_job(&write_txn, &old)?;
+            }
+            index_job(&write_txn, job)?;
         }
         write_txn.commit()?;
         Ok(())
     }
+    pub fn remove_job(&self, id: i32) -> Result<()> {
+        let write_txn = self.inner.begin_write()?;
+        {
+            let mut jobs = write_txn.open_table(JOBS)?;
+            let old = match jobs.remove(id)? {
+                Some(v) => Some(serde_json::from_slice::<Job>(&v.value())?),
+                None => None
+            };
+            drop(jobs);
+            if let Some(old) = old {
+                unindex_job(&write_txn, &old)?;
+            }
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+    pub fn get_job(&self, id: i32) -> Result<Option<Job>> {
+        let read_txn = self.inner.begin_read()?;
+        {
+            let jobs = read_txn.open_table(JOBS)?;
+            match jobs.get(id)? {
+                Some(v) => Ok(Some(serde_json::from_slice::<Job>(&v.value())?)),
+                None => Ok(None)
+            }
+        }
+    }
     pub fn find_job<F>(&self, mut predicate: F) -> Result<Option<Job>> where F: FnMut(&Job) -> Result<bool> {
         let read_txn = self.inner.begin_read()?;
         {
@@ -70,17 +158,69 @@ impl Database {
         }
         Ok(None)
     }
-    pub fn find_jobs<F>(&self, mut predicate: F) -> Result<Vec<Job>> where F: FnMut(&Job) -> Result<bool> {
+    pub fn find_jobs<F>(&self, predicate: F) -> Result<Vec<Job>> where F: FnMut(&Job) -> Result<bool> {
+        self.find_jobs_in(JobIndex::All, predicate)
+    }
+    // jobs come in order of id, whichever index is used
+    pub fn find_jobs_in<F>(&self, index: JobIndex, mut predicate: F) -> Result<Vec<Job>> where F: FnMut(&Job) -> Result<bool> {
         let read_txn = self.inner.begin_read()?;
         let mut result = vec![];
         {
             let jobs = read_txn.open_table(JOBS)?;
-            for kv in jobs.range::<i32>(..)? {
-                if kv.is_err() { break }
-                let kv = kv.unwrap();
-                let job: Job = serde_json::from_slice(&kv.1.value())?;
-                if predicate(&job)? {
-                    result.push(job);
+            let mut ids = vec![];
+            match index {
+                JobIndex::All => {
+                    for kv in jobs.range::<i32>(..)? {
+                        if kv.is_err() { break }
+                        let kv = kv.unwrap();
+                        let job: Job = serde_json::from_slice(&kv.1.value())?;
+                        if predicate(&job)? {
+                            result.push(job);
+                        }
+                    }
+                    return Ok(result);
+                }
+                JobIndex::User(user_id) => {
+                    let index = read_txn.open_table(JOBS_BY_USER_PROBLEM)?;
+                    for kv in index.range((user_id, i32::MIN, i32::MIN)..=(user_id, i32::MAX, i32::MAX))? {
+                        ids.push(kv?.0.value().2);
+                    }
+                    ids.sort();
+                }
+                JobIndex::UserProblem(user_id, problem_id) => {
+                    let index = read_txn.open_table(JOBS_BY_USER_PROBLEM)?;
+                    for kv in index.range((user_id, problem_id, i32::MIN)..=(user_id, problem_id, i32::MAX))? {
+                        ids.push(kv?.0.value().2);
+                    }
+                }
+                JobIndex::Problem(problem_id) => {
+                    let index = read_txn.open_table(JOBS_BY_PROBLEM)?;
+                    for kv in index.range((problem_id, i32::MIN)..=(problem_id, i32::MAX))? {
+                        ids.push(kv?.0.value().1);
+                    }
+                }
+                JobIndex::Contest(contest_id) => {
+                    let index = read_txn.open_table(JOBS_BY_CONTEST)?;
+                    for kv in index.range((contest_id, i32::MIN)..=(contest_id, i32::MAX))? {
+                        ids.push(kv?.0.value().1);
+                    }
+                }
+                JobIndex::CreatedTime(from, to) => {
+                    let from = from.map_or(i64::MIN, |from| from.timestamp_micros());
+                    let to = to.map_or(i64::MAX, |to| to.timestamp_micros());
+                    let index = read_txn.open_table(JOBS_BY_TIME)?;
+                    for kv in index.range((from, i32::MIN)..=(to, i32::MAX))? {
+                        ids.push(kv?.0.value().1);
+                    }
+                    ids.sort();
+                }
+            }
+            for id in ids {
+                if let Some(v) = jobs.get(id)? {
+                    let job: Job = serde_json::from_slice(&v.value())?;
+                    if predicate(&job)? {
+                        result.push(job);
+                    }
                 }
             }
         }
@@ -0,0 +1,75 @@
+use std::convert::Infallible;
+use std::sync::Mutex;
+
+use actix_web::web::Bytes;
+use futures_channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+
+use crate::models::*;
+
+type Chunk = std::result::Result<Bytes, Infallible>;
+
+pub type EventStream = UnboundedReceiver<Chunk>;
+
+struct Subscriber {
+    // None follows every job
+    job_id: Option<i32>,
+    // a job hidden by a freeze is followed without its verdict
+    hidden: bool,
+    sender: UnboundedSender<Chunk>,
+}
+
+pub struct Events {
+    subscribers: Mutex<Vec<Subscriber>>,
+}
+
+fn event(job: &Job, hidden: bool) -> Chunk {
+    let data = if hidden {
+        let mut job = job.clone();
+        job.hide_verdict();
+        serde_json::to_string(&job)
+    } else {
+        serde_json::to_string(job)
+    };
+    Ok(Bytes::from(format!("event: job\ndata: {}\n\n", data.unwrap())))
+}
+
+fn is_done(job: &Job) -> bool {
+    job.state == JobStatus::Finished || job.state == JobStatus::Canceled
+}
+
+impl Events {
+    pub fn new() -> Self {
+        Self {
+            subscribers: Mutex::new(vec![])
+        }
+    }
+    // the stream of a single job starts from its current state and ends when it is done
+    pub fn subscribe_job(&self, job: &Job, hidden: bool) -> EventStream {
+        let (sender, receiver) = unbounded();
+        let _ = sender.unbounded_send(event(job, hidden));
+        if !is_done(job) {
+            self.subscribers.lock().unwrap().push(Subscriber { job_id: Some(job.id), hidden, sender });
+        }
+        receiver
+    }
+    pub fn subscribe_all(&self) -> EventStream {
+        let (sender, receiver) = unbounded();
+        self.subscribers.lock().unwrap().push(Subscriber { job_id: None, hidden: false, sender });
+        receiver
+    }
+    pub fn publish(&self, job: &Job) {
+        // the job is serialized only once someone follows it, and at most once for each way of showing it
+        let mut chunks: [Option<Chunk>; 2] = [None, None];
+        let mut chunk = |hidden: bool| chunks[hidden as usize].get_or_insert_with(|| event(job, hidden)).clone();
+        // disconnected clients are dropped here, when a send first fails
+        self.subscribers.lock().unwrap().retain(|subscriber| {
+            match subscriber.job_id {
+                None => subscriber.sender.unbounded_send(chunk(subscriber.hidden)).is_ok(),
+                Some(id) if id == job.id => {
+                    subscriber.sender.unbounded_send(chunk(subscriber.hidden)).is_ok() && !is_done(job)
+                }
+                Some(_) => !subscriber.sender.is_closed()
+            }
+        });
+    }
+}
@@ -0,0 +1,205 @@
+use std::env::temp_dir;
+use std::ffi::{CStr, CString};
+use std::fs::{create_dir_all, write, File};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::ptr::null;
+
+use crate::models::*;
+
+pub const NAMESPACES: libc::c_int =
+    libc::CLONE_NEWUSER | libc::CLONE_NEWNS | libc::CLONE_NEWPID |
+    libc::CLONE_NEWNET | libc::CLONE_NEWIPC | libc::CLONE_NEWUTS;
+
+const SANDBOX_DIR: &CStr = c"/sandbox";
+const DEFAULT_ROOT: &[&str] = &["/bin", "/lib", "/lib64", "/usr"];
+const DEVICES: &[&str] = &["/dev/null", "/dev/zero", "/dev/random", "/dev/urandom"];
+const NOBODY: u32 = 65534;
+
+// flags the kernel locks on a bind mount, so a read-only remount has to carry them over
+const LOCKED_FLAGS: libc::c_ulong =
+    libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC |
+    libc::MS_NOATIME | libc::MS_NODIRATIME | libc::MS_RELATIME;
+
+fn cstring(path: impl AsRef<Path>) -> Result<CString> {
+    CString::new(path.as_ref().as_os_str().as_bytes()).map_err(|_| {
+        ERR_INTERNAL.clone().with_message(format!("Invalid path {}.", path.as_ref().display()))
+    })
+}
+
+fn readonly_flags(path: &CString) -> libc::c_ulong {
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    let locked = if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } == 0 { stat.f_flag & LOCKED_FLAGS } else { 0 };
+    libc::MS_REMOUNT | libc::MS_BIND | libc::MS_RDONLY | locked
+}
+
+fn relative(path: &Path) -> &Path {
+    path.strip_prefix("/").unwrap_or(path)
+}
+
+struct Mount {
+    source: CString,
+    target: CString,
+    is_file: bool,
+    remount_flags: Option<libc::c_ulong>,
+}
+
+// everything the child needs is prepared here, since it must not allocate after clone
+pub struct Isolation {
+    workdir: PathBuf,
+    staging: CString,
+    rootfs: Option<CString>,
+    root_flags: libc::c_ulong,
+    directories: Vec<CString>,
+    mounts: Vec<Mount>,
+    proc: CString,
+}
+
+impl Isolation {
+    pub fn new(rootfs: Option<&str>, bind_mounts: &[BindMount], workdir: &Path, writable: bool) -> Result<Self> {
+        let staging = temp_dir().join("oj-root");
+        create_dir_all(&staging)?;
+        let mut binds = vec![];
+        if rootfs.is_none() {
+            for dir in DEFAULT_ROOT.iter().filter(|dir| Path::new(dir).exists()) {
+                binds.push((PathBuf::from(dir), PathBuf::from(dir), false));
+            }
+        }
+        for bind in bind_mounts {
+            let target = bind.target.as_ref().unwrap_or(&bind.source);
+            binds.push((PathBuf::from(&bind.source), PathBuf::from(target), bind.writable));
+        }
+        binds.push((workdir.to_path_buf(), PathBuf::from(SANDBOX_DIR.to_str().unwrap()), writable));
+        for device in DEVICES.iter().filter(|device| Path::new(device).exists()) {
+            binds.push((PathBuf::from(device), PathBuf::from(device), true));
+        }
+
+        let mut directories: Vec<PathBuf> = vec![];
+        let mut add_directory = |dir: &Path| {
+            for ancestor in dir.ancestors().collect::<Vec<_>>().into_iter().rev() {
+                if !ancestor.as_os_str().is_empty() && !directories.iter().any(|d| d == ancestor) {
+                    directories.push(ancestor.to_path_buf());
+                }
+            }
+        };
+        add_directory(Path::new("proc"));
+        for (source, target, _) in &binds {
+            let target = relative(target);
+            add_directory(if source.is_dir() { target } else { target.parent().unwrap_or(Path::new("")) });
+        }
+
+        // a configured rootfs is shared and read-only inside, so its mount points are made up front
+        let rootfs = match rootfs {
+            Some(rootfs) => {
+                for dir in &directories {
+                    create_dir_all(Path::new(rootfs).join(dir))?;
+                }
+                for (source, target, _) in &binds {
+                    let path = Path::new(rootfs).join(relative(target));
+                    if !source.is_dir() && !path.exists() {
+                        File::create(path)?;
+                    }
+                }
+                Some(cstring(rootfs)?)
+            }
+            None => None
+        };
+        let mut mounts = vec![];
+        for (source, target, writable) in binds {
+            let is_file = !source.is_dir();
+            let source = cstring(&source)?;
+            mounts.push(Mount {
+                remount_flags: if writable { None } else { Some(readonly_flags(&source)) },
+                source,
+                target: cstring(staging.join(relative(&target)))?,
+                is_file,
+            });
+        }
+        Ok(Self {
+            workdir: workdir.to_path_buf(),
+            root_flags: match &rootfs {
+                Some(rootfs) => readonly_flags(rootfs),
+                None => libc::MS_REMOUNT | libc::MS_BIND | libc::MS_RDONLY
+            },
+            rootfs,
+            directories: directories.iter().map(|dir| cstring(staging.join(dir))).collect::<Result<_>>()?,
+            mounts,
+            proc: cstring(staging.join("proc"))?,
+            staging: cstring(&staging)?,
+        })
+    }
+    pub fn inner_path(&self, path: &str) -> String {
+        match Path::new(path).strip_prefix(&self.workdir) {
+            Ok(rest) if rest.as_os_str().is_empty() => SANDBOX_DIR.to_str().unwrap().into(),
+            Ok(rest) => Path::new(SANDBOX_DIR.to_str().unwrap()).join(rest).to_str().unwrap().into(),
+            Err(_) => path.into()
+        }
+    }
+    // the host user and group a sandboxed program runs as, which has to own whatever it may write to
+    pub fn host_ids() -> (u32, u32) {
+        let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        if uid == 0 { (NOBODY, NOBODY) } else { (uid, gid) }
+    }
+    // maps the namespace root to an unprivileged user, called by the parent after clone
+    pub fn map_ids(&self, pid: libc::pid_t) -> Result<()> {
+        let (uid, gid) = Self::host_ids();
+        write(format!("/proc/{}/setgroups", pid), "deny")?;
+        write(format!("/proc/{}/uid_map", pid), format!("0 {} 1", uid))?;
+        write(format!("/proc/{}/gid_map", pid), format!("0 {} 1", gid))?;
+        Ok(())
+    }
+    // called in the cloned child, already inside the new namespaces
+    pub unsafe fn enter(&self) -> bool {
+        // the child keeps its host ids until it switches to the mapped namespace root
+        if libc::setresgid(0, 0, 0) != 0 || libc::setresuid(0, 0, 0) != 0 {
+            return false;
+        }
+        if libc::mount(null(), c"/".as_ptr(), null(), libc::MS_REC | libc::MS_PRIVATE, null()) != 0 {
+            return false;
+        }
+        let root_mounted = match &self.rootfs {
+            Some(rootfs) => libc::mount(rootfs.as_ptr(), self.staging.as_ptr(), null(), libc::MS_BIND | libc::MS_REC, null()),
+            None => libc::mount(c"tmpfs".as_ptr(), self.staging.as_ptr(), c"tmpfs".as_ptr(), 0, c"mode=755".as_ptr() as _)
+        };
+        if root_mounted != 0 {
+            return false;
+        }
+        for dir in &self.directories {
+            if libc::mkdir(dir.as_ptr(), 0o755) != 0 && *libc::__errno_location() != libc::EEXIST {
+                return false;
+            }
+        }
+        for mount in &self.mounts {
+            if mount.is_file {
+                let fd = libc::open(mount.target.as_ptr(), libc::O_RDONLY | libc::O_CREAT | libc::O_CLOEXEC, 0o644);
+                if fd < 0 {
+                    return false;
+                }
+                libc::close(fd);
+            }
+            if libc::mount(mount.source.as_ptr(), mount.target.as_ptr(), null(), libc::MS_BIND | libc::MS_REC, null()) != 0 {
+                return false;
+            }
+            if let Some(flags) = mount.remount_flags {
+                if libc::mount(null(), mount.target.as_ptr(), null(), flags, null()) != 0 {
+                    return false;
+                }
+            }
+        }
+        if libc::mount(c"proc".as_ptr(), self.proc.as_ptr(), c"proc".as_ptr(), libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC, null()) != 0 {
+            return false;
+        }
+        // stack the old root under the new one, then detach it
+        if
+            libc::chdir(self.staging.as_ptr()) != 0 ||
+            libc::syscall(libc::SYS_pivot_root, c".".as_ptr(), c".".as_ptr()) != 0 ||
+            libc::umount2(c".".as_ptr(), libc::MNT_DETACH) != 0 ||
+            libc::chdir(c"/".as_ptr()) != 0
+        {
+            return false;
+        }
+        libc::mount(null(), c"/".as_ptr(), null(), self.root_flags, null()) == 0 &&
+        libc::sethostname(c"sandbox".as_ptr(), 7) == 0 &&
+        libc::chdir(SANDBOX_DIR.as_ptr()) == 0
+    }
+}
@@ -1,48 +1,237 @@
-use std::fs::File;
-use std::os::fd::AsRawFd;
-use std::process::Command;
-use std::sync::{Arc, Mutex};
-use std::thread::spawn;
+use std::ffi::CString;
+use std::fs::{metadata, read, File};
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::Path;
 use std::time::Instant;
 
+use crate::cgroup::Cgroup;
+use crate::isolation::{Isolation, NAMESPACES};
 use crate::models::*;
+use crate::seccomp::SeccompFilter;
 
-pub fn standard_judge(got: &str, expected: &str) -> Status {
+const EXCERPT_LENGTH: usize = 40;
+
+// a quoted window of the line around a column, counted in characters
+fn excerpt(line: &str, column: usize) -> String {
+    let start = column.saturating_sub(EXCERPT_LENGTH / 2);
+    let mut excerpt = line.chars().skip(start).take(EXCERPT_LENGTH).collect::<String>();
+    if start > 0 {
+        excerpt.insert_str(0, "...");
+    }
+    if line.chars().count() > start + EXCERPT_LENGTH {
+        excerpt.push_str("...");
+    }
+    format!("{:?}", excerpt)
+}
+
+fn line_diff(got: &[&str], expected: &[&str]) -> (Status, String) {
+    for (i, (got_line, expected_line)) in got.iter().zip(expected).enumerate() {
+        if got_line != expected_line {
+            let column = got_line.chars().zip(expected_line.chars()).take_while(|(a, b)| a == b).count();
+            return (Status::WrongAnswer, format!(
+                "Line {}, column {} differs: expected {}, got {}.",
+                i + 1, column + 1, excerpt(expected_line, column), excerpt(got_line, column)
+            ));
+        }
+    }
+    if got.len() < expected.len() {
+        (Status::WrongAnswer, format!("Output too short: line {} missing, expected {}.", got.len() + 1, excerpt(expected[got.len()], 0)))
+    }
+    else if got.len() > expected.len() {
+        (Status::WrongAnswer, format!("Extra output on line {}: {}.", expected.len() + 1, excerpt(got[expected.len()], 0)))
+    }
+    else {
+        (Status::Accepted, "".into())
+    }
+}
+
+pub fn standard_judge(got: &str, expected: &str) -> (Status, String) {
     let got = 
         got.trim_end()
         .split("\n")
+        .map(|line| line.trim_end())
         .collect::<Vec<&str>>();
     let expected = 
         expected.trim_end()
         .split("\n")
+        .map(|line| line.trim_end())
         .collect::<Vec<&str>>();
-    if got.len() != expected.len() { return Status::WrongAnswer; }
-    for (mut got_line, mut expected_line) in got.into_iter().zip(expected.into_iter()) {
-        got_line = got_line.trim_end();
-        expected_line = expected_line.trim_end();
-        if got_line != expected_line {
-            return Status::WrongAnswer;
+    line_diff(&got, &expected)
+}
+
+pub fn strict_judge(got: &str, expected: &str) -> (Status, String) {
+    if got == expected { 
+        return (Status::Accepted, "".into());
+    }
+    line_diff(&got.split("\n").collect::<Vec<&str>>(), &expected.split("\n").collect::<Vec<&str>>())
+}
+
+pub fn token_judge(got: &str, expected: &str) -> Status {
+    if got.split_whitespace().eq(expected.split_whitespace()) { Status::Accepted } else { Status::WrongAnswer }
+}
+
+pub fn case_insensitive_judge(got: &str, expected: &str) -> Status {
+    standard_judge(&got.to_lowercase(), &expected.to_lowercase()).0
+}
+
+pub fn unordered_lines_judge(got: &str, expected: &str) -> Status {
+    fn sorted_lines(text: &str) -> Vec<&str> {
+        let mut lines = text.trim_end().split("\n").map(|line| line.trim_end()).collect::<Vec<&str>>();
+        lines.sort_unstable();
+        lines
+    }
+    if sorted_lines(got) == sorted_lines(expected) { Status::Accepted } else { Status::WrongAnswer }
+}
+
+fn set_limit(resource: libc::__rlimit_resource_t, limit: u64) {
+    if limit > 0 {
+        let limit = libc::rlimit { rlim_cur: limit, rlim_max: limit };
+        unsafe { libc::setrlimit(resource, &limit) };
+    }
+}
+
+// tokens paired with the line they are on, to locate a mismatch
+fn tokens(text: &str) -> Vec<(usize, &str)> {
+    text.lines().enumerate().flat_map(|(i, line)| {
+        line.split_whitespace().map(move |token| (i + 1, token))
+    }).collect()
+}
+
+fn float_equal(got: &str, expected: &str, absolute: f64, relative: f64) -> bool {
+    if got == expected {
+        return true;
+    }
+    match (got.parse::<f64>(), expected.parse::<f64>()) {
+        (Ok(got), Ok(expected)) => {
+            let error = (got - expected).abs();
+            error <= absolute || error <= relative * expected.abs()
         }
+        _ => false
     }
-    Status::Accepted
 }
 
-pub fn strict_judge(got: &str, expected: &str) -> Status {
-    if got == expected { Status::Accepted } else { Status::WrongAnswer }
+// tokens that are not numbers must match exactly
+pub fn float_judge(got: &str, expected: &str, absolute: f64, relative: f64) -> (Status, String) {
+    let got = tokens(got);
+    let expected = tokens(expected);
+    for (i, ((line, got_token), (_, expected_token))) in got.iter().zip(&expected).enumerate() {
+        if !float_equal(got_token, expected_token, absolute, relative) {
+            return (Status::WrongAnswer, format!("Token {} on line {}: expected {}, got {}.", i + 1, line, expected_token, got_token));
+        }
+    }
+    if got.len() < expected.len() {
+        (Status::WrongAnswer, format!("Token {}: expected {}, got end of output.", got.len() + 1, expected[got.len()].1))
+    }
+    else if got.len() > expected.len() {
+        let (line, got_token) = got[expected.len()];
+        (Status::WrongAnswer, format!("Token {} on line {}: expected end of output, got {}.", expected.len() + 1, line, got_token))
+    }
+    else {
+        (Status::Accepted, "".into())
+    }
 }
 
-impl Language {
-    pub fn compile(&self, src: &str, dst: &str) -> bool {
-        let command = self.expand_command(src, dst);
-        let status = 
-            Command::new(&command[0])
-            .args(&command[1..])
-            .status();
-        return status.is_ok_and(|code| code.success());
+const TESTLIB_OK: i32 = 0;
+const TESTLIB_WRONG_ANSWER: i32 = 1;
+const TESTLIB_PRESENTATION_ERROR: i32 = 2;
+const TESTLIB_POINTS: i32 = 7;
+
+fn partial_status(fraction: f32) -> Status {
+    if fraction >= 1.0 {
+        Status::Accepted
+    }
+    else if fraction > 0.0 {
+        Status::PartiallyCorrect
+    }
+    else {
+        Status::WrongAnswer
+    }
+}
+
+// a checker's status, the fraction of the case score it gives, and its info
+pub type Verdict = (Status, f32, String);
+
+impl CheckerProtocol {
+    // the status comes with the fraction of the case score it earns
+    pub fn parse(&self, exit_code: i32, stdout: &str, stderr: &str) -> Verdict {
+        match self {
+            Self::Native => {
+                let lines = stdout.split_terminator("\n").collect::<Vec<&str>>();
+                if lines.len() != 2 && lines.len() != 3 {
+                    return (Status::SpecialJudgeError, 0.0, "".into());
+                }
+                let status = serde_json::from_str(&format!("\"{}\"", lines[0]));
+                let fraction = lines.get(2).map(|line| line.trim().parse::<f32>());
+                match (status, fraction) {
+                    (Ok(Status::Accepted | Status::PartiallyCorrect), Some(Ok(fraction))) if (0.0..=1.0).contains(&fraction) => {
+                        (partial_status(fraction), fraction, lines[1].into())
+                    }
+                    (Ok(Status::PartiallyCorrect), _) | (_, Some(_)) => (Status::SpecialJudgeError, 0.0, "".into()),
+                    (Ok(status), None) => (status, 1.0, lines[1].into()),
+                    (Err(_), None) => (Status::SpecialJudgeError, 0.0, "".into())
+                }
+            }
+            Self::Testlib => {
+                let info = stderr.trim().to_string();
+                match exit_code {
+                    TESTLIB_OK => (Status::Accepted, 1.0, info),
+                    TESTLIB_WRONG_ANSWER | TESTLIB_PRESENTATION_ERROR => (Status::WrongAnswer, 0.0, info),
+                    // reported as `points <value> <message>`, the value being a fraction of the case score
+                    TESTLIB_POINTS => {
+                        let rest = info.strip_prefix("points").unwrap_or(&info).trim_start();
+                        let (points, message) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                        match points.parse::<f32>() {
+                            Ok(points) if (0.0..=1.0).contains(&points) => (partial_status(points), points, message.trim().into()),
+                            _ => (Status::SpecialJudgeError, 0.0, info)
+                        }
+                    }
+                    // including 3, the checker failing by its own account
+                    _ => (Status::SpecialJudgeError, 0.0, info)
+                }
+            }
+        }
     }
 }
 
-const BANNED_SYSCALLS: &[u64] = &[435];
+impl Language {
+    // compiler output goes through files, so that the output limit bounds the diagnostics too;
+    // they are only given back from an isolated compiler, as any other could #include whatever the server can read
+    pub fn compile(&self, sandbox: &Sandbox, src: &str, extra: &[String], dst: &str, log_file: &str, err_file: &str) -> (bool, String) {
+        let extra = extra.iter().map(|file| sandbox.path(file)).collect::<Vec<_>>();
+        let command = self.expand_command(&sandbox.path(src), &extra, &sandbox.path(dst));
+        let limits = &self.compile_limits;
+        let (result, _) = limits.as_case().execute(sandbox, &command, "/dev/null", log_file, Some(err_file));
+
+        let mut message = String::new();
+        if sandbox.isolation.is_some() {
+            for file in [log_file, err_file] {
+                message.push_str(&String::from_utf8_lossy(&read(file).unwrap_or_default()));
+            }
+            // the working directory means nothing to the user
+            if let Some(dir) = Path::new(src).parent().and_then(|dir| dir.to_str()).map(|dir| sandbox.path(dir)) {
+                message = message.replace(&format!("{}/", dir), "").replace(&dir, ".");
+            }
+        }
+        // a compiler that hit the output limit may only report its own failure, so the files themselves tell
+        let output_full = limits.output_limit > 0 && [log_file, err_file, dst].iter().any(|file| {
+            metadata(file).is_ok_and(|metadata| metadata.len() >= limits.output_limit)
+        });
+        match result {
+            Ok(0) => {}
+            Err(Status::TimeLimitExceeded) => message.push_str("Compilation time limit exceeded.\n"),
+            Err(Status::MemoryLimitExceeded) => message.push_str("Compilation memory limit exceeded.\n"),
+            Err(Status::SystemError) => message.push_str(&format!("Failed to run {}.\n", command[0])),
+            _ if output_full => message.push_str("Compilation output limit exceeded.\n"),
+            _ => {}
+        }
+        if message.len() > limits.message_limit {
+            message.truncate(message.floor_char_boundary(limits.message_limit));
+            message.push_str("\n(truncated)");
+        }
+        (result == Ok(0), message)
+    }
+}
 
 #[derive(Clone, Copy)]
 struct Timer {
@@ -63,154 +252,468 @@ impl Timer {
 
 pub struct Resources {
     pub time: u64,
+    pub wall_time: u64,
     pub memory: u64,
 }
 
-impl Case {
-    pub fn run(&self, exe_file: &str, in_file: &str, out_file: &str) -> (std::result::Result<(), Status>, Resources) {
-        unsafe {
-            let pid = libc::fork();
-            if pid > 0 { // parent
-                let timer = Timer::new();
-                let timeout = Arc::new(Mutex::new(false));
-                let timeout_clone = Arc::clone(&timeout);
-                let timer_stopped = Arc::new(Mutex::new(false));
-                let timer_stopped_clone = Arc::clone(&timer_stopped);
-                let mut join_handle = None;
-                if self.time_limit > 0 {
-                    let time_limit = self.time_limit;
-                    join_handle = Some(spawn(move || {
-                        while !*timer_stopped.lock().unwrap() && timer.elapsed() <= time_limit {}
-                        if !*timer_stopped.lock().unwrap() {
-                            {
-                                *timeout.lock().unwrap() = true;
-                            }
-                            libc::kill(pid, libc::SIGTERM);
-                            //println!("Killed");
-                        }
-                    }));
-                }
-                let mut status = 0;
-                let case_status: Status;
-                let mut memory_used : u64;
-                let mut regs = libc::user_regs_struct { 
-                    r15: 0, r14: 0, r13: 0, r12: 0, rbp: 0, rbx: 0, r11: 0, r10: 0, r9: 0, r8: 0, 
-                    rax: 0, rcx: 0, rdx: 0, rsi: 0, rdi: 0, orig_rax: 0, rip: 0, cs: 0, eflags: 0, 
-                    rsp: 0, ss: 0, fs_base: 0, gs_base: 0, ds: 0, es: 0, fs: 0, gs: 0 
-                };
-                let mut ru = libc::rusage { 
-                    ru_utime: libc::timeval { tv_sec: 0, tv_usec: 0 }, 
-                    ru_stime: libc::timeval { tv_sec: 0, tv_usec: 0 }, 
-                    ru_maxrss: 0, ru_ixrss: 0, ru_idrss: 0, ru_isrss: 0, ru_minflt: 0, 
-                    ru_majflt: 0, ru_nswap: 0, ru_inblock: 0, ru_oublock: 0, ru_msgsnd: 0, 
-                    ru_msgrcv: 0, ru_nsignals: 0, ru_nvcsw: 0, ru_nivcsw: 0 
-                };
-                libc::wait(&mut status);
-                loop {
-                    libc::ptrace(libc::PTRACE_SYSCALL, pid, 0, 0);
-                    libc::wait4(pid, &mut status, libc::WUNTRACED, &mut ru);
-                    memory_used = (ru.ru_maxrss * 1000) as _;
-
-                    if libc::WIFEXITED(status) {
-                        if libc::WEXITSTATUS(status) == 0 {
-                            case_status = Status::Accepted;
-                        }
-                        else {
-                            case_status = Status::RuntimeError;
-                        }
-                        break;
-                    }
-                    if libc::WIFSIGNALED(status) {
-                        libc::ptrace(libc::PTRACE_KILL, pid, 0, 0);
-                        if libc::WTERMSIG(status) == libc::SIGXCPU {
-                            case_status = Status::TimeLimitExceeded;
-                        }
-                        else if 
-                            libc::WTERMSIG(status) == libc::SIGSEGV && 
-                            self.memory_limit > 0 && memory_used > self.memory_limit 
-                        {
-                            case_status = Status::MemoryLimitExceeded;
-                        }
-                        else {
-                            case_status = Status::RuntimeError;
-                        }
-                        break;
-                    }
+pub struct Sandbox<'a> {
+    pub filter: SeccompFilter,
+    pub cgroup_root: Option<&'a Path>,
+    pub isolation: Option<Isolation>,
+    pub cpu: Option<usize>,
+}
 
-                    if 
-                        libc::WIFSTOPPED(status) && 
-                        libc::WSTOPSIG(status) != libc::SIGTRAP && 
-                        libc::WSTOPSIG(status) != libc::SIGCHLD 
-                    {
-                        libc::ptrace(libc::PTRACE_KILL, pid, 0, 0);
-                        if libc::WSTOPSIG(status) == libc::SIGXCPU {
-                            case_status = Status::TimeLimitExceeded;
-                        }
-                        else if 
-                            libc::WSTOPSIG(status) == libc::SIGSEGV && 
-                            self.memory_limit > 0 && memory_used > self.memory_limit 
-                        {
-                            case_status = Status::MemoryLimitExceeded;
-                        }
-                        else {
-                            case_status = Status::RuntimeError;
-                        }
-                        break;
-                    }
+impl Sandbox<'_> {
+    // the path a sandboxed program sees for a file in the working directory
+    pub fn path(&self, path: &str) -> String {
+        match &self.isolation {
+            Some(isolation) => isolation.inner_path(path),
+            None => path.into()
+        }
+    }
+}
 
-                    if self.memory_limit > 0 && memory_used > self.memory_limit {
-                        libc::ptrace(libc::PTRACE_KILL, pid, 0, 0);
-                        case_status = Status::MemoryLimitExceeded;
-                        break;
-                    }
+fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    unsafe {
+        if libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])))
+    }
+}
 
-                    libc::ptrace(libc::PTRACE_GETREGS, pid, 0, &mut regs);
-                    if BANNED_SYSCALLS.contains(&regs.orig_rax) {
-                        libc::ptrace(libc::PTRACE_KILL, pid, 0, 0);
-                        libc::waitpid(pid, &mut status, libc::WUNTRACED);
-                        case_status = Status::RuntimeError;
-                        break;
-                    }
-                }
-                *timer_stopped_clone.lock().unwrap() = true;
-                if let Some(handle) = join_handle {
-                    handle.join().unwrap();
-                }
-                if *timeout_clone.lock().unwrap() || (self.time_limit > 0 && timer.elapsed() > self.time_limit) {
-                    (Err(Status::TimeLimitExceeded), Resources {
-                        time: timer.elapsed(),
-                        memory: memory_used
-                    })
+// a sandboxed child that has been released to exec
+struct Process<'a> {
+    case: &'a Case,
+    pid: libc::pid_t,
+    pidfd: OwnedFd,
+    cgroup: Option<Cgroup>,
+    timer: Timer,
+}
+
+impl Process<'_> {
+    fn kill(&self) {
+        // the child may be the init of a pid namespace, which ignores SIGTERM
+        unsafe { libc::kill(self.pid, libc::SIGKILL) };
+    }
+    // reaps the child and checks it against the case limits, given when it exited or None if it was killed at the limit,
+    // giving back the exit code of a program that kept to them
+    fn finish(self, exited_at: Option<u64>) -> (std::result::Result<i32, Status>, Resources) {
+        let case = self.case;
+        let mut status = 0;
+        let exit: std::result::Result<i32, Status>;
+        let mut ru = libc::rusage { 
+            ru_utime: libc::timeval { tv_sec: 0, tv_usec: 0 }, 
+            ru_stime: libc::timeval { tv_sec: 0, tv_usec: 0 }, 
+            ru_maxrss: 0, ru_ixrss: 0, ru_idrss: 0, ru_isrss: 0, ru_minflt: 0, 
+            ru_majflt: 0, ru_nswap: 0, ru_inblock: 0, ru_oublock: 0, ru_msgsnd: 0, 
+            ru_msgrcv: 0, ru_nsignals: 0, ru_nvcsw: 0, ru_nivcsw: 0 
+        };
+        unsafe { libc::wait4(self.pid, &mut status, 0, &mut ru) };
+        let timed_out = exited_at.is_none();
+        let wall_time = exited_at.unwrap_or_else(|| self.timer.elapsed());
+        let wall_time_limit = case.effective_wall_time_limit();
+        let (cpu_time, memory_peak, oom_killed) = match &self.cgroup {
+            Some(cgroup) => (cgroup.cpu_time(), cgroup.memory_peak(), cgroup.oom_killed()),
+            None => (None, None, false)
+        };
+        let rusage_time = |tv: libc::timeval| (tv.tv_sec * 1000000 + tv.tv_usec) as u64;
+        let resources = Resources {
+            time: cpu_time.unwrap_or(rusage_time(ru.ru_utime) + rusage_time(ru.ru_stime)),
+            wall_time,
+            memory: memory_peak.unwrap_or(ru.ru_maxrss as u64 * 1024)
+        };
+
+        if oom_killed {
+            exit = Err(Status::MemoryLimitExceeded);
+        }
+        else if libc::WIFEXITED(status) {
+            exit = Ok(libc::WEXITSTATUS(status));
+        }
+        else if libc::WTERMSIG(status) == libc::SIGSYS {
+            exit = Err(Status::RestrictedFunction);
+        }
+        else if libc::WTERMSIG(status) == libc::SIGXCPU {
+            exit = Err(Status::TimeLimitExceeded);
+        }
+        else if 
+            self.cgroup.is_none() &&
+            libc::WTERMSIG(status) == libc::SIGSEGV && 
+            case.memory_limit > 0 && resources.memory > case.memory_limit 
+        {
+            exit = Err(Status::MemoryLimitExceeded);
+        }
+        else {
+            exit = Err(Status::RuntimeError);
+        }
+        if 
+            timed_out || 
+            (case.cpu_time_limit > 0 && resources.time > case.cpu_time_limit) ||
+            (wall_time_limit > 0 && wall_time > wall_time_limit)
+        {
+            (Err(Status::TimeLimitExceeded), resources)
+        }
+        else if case.memory_limit > 0 && resources.memory > case.memory_limit {
+            (Err(Status::MemoryLimitExceeded), resources)
+        }
+        else {
+            (exit, resources)
+        }
+    }
+}
+
+// sleeps until every process has exited or the wall clock limit has passed, in which case the ones left are killed;
+// gives back the wall time each process exited at, or None for the killed ones
+fn wait_all(processes: &[&Process], wall_time_limit: u64) -> Vec<Option<u64>> {
+    // a pidfd turns readable once its child exits, and poll skips the negative ones
+    let mut pollfds = processes.iter().map(|process| {
+        libc::pollfd { fd: process.pidfd.as_raw_fd(), events: libc::POLLIN, revents: 0 }
+    }).collect::<Vec<_>>();
+    let timer = processes[0].timer;
+    let mut exited = vec![None; processes.len()];
+    while pollfds.iter().any(|pollfd| pollfd.fd >= 0) {
+        let timeout = if wall_time_limit > 0 {
+            wall_time_limit.saturating_sub(timer.elapsed()).div_ceil(1000).min(i32::MAX as u64) as libc::c_int
+        } else {
+            -1
+        };
+        let ready = unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as _, timeout) };
+        if ready < 0 {
+            if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            break;
+        }
+        if ready == 0 {
+            for (process, pollfd) in processes.iter().zip(&pollfds) {
+                if pollfd.fd >= 0 {
+                    process.kill();
                 }
-                else if case_status == Status::Accepted {
-                    (Ok(()), Resources {
-                        time: timer.elapsed(),
-                        memory: memory_used
-                    })
+            }
+            return exited;
+        }
+        for (pollfd, exited) in pollfds.iter_mut().zip(&mut exited) {
+            if pollfd.revents != 0 {
+                pollfd.fd = -1;
+                *exited = Some(timer.elapsed());
+            }
+        }
+    }
+    // should poll fail, the ones left count as exited by now, and are waited for when reaped
+    exited.iter().map(|exited| Some(exited.unwrap_or_else(|| timer.elapsed()))).collect()
+}
+
+pub fn checker_failure(status: Status) -> String {
+    let reason = match status {
+        Status::TimeLimitExceeded => "exceeded its time limit",
+        Status::MemoryLimitExceeded => "exceeded its memory limit",
+        Status::RestrictedFunction => "made a restricted system call",
+        Status::SystemError => "could not be started",
+        _ => "crashed"
+    };
+    format!("Special judge {}.", reason)
+}
+
+impl Case {
+    // a program that sleeps uses no CPU time, so it always gets a wall clock limit
+    pub fn effective_wall_time_limit(&self) -> u64 {
+        if self.wall_time_limit > 0 { self.wall_time_limit } else { self.cpu_time_limit * 2 }
+    }
+    pub fn run(&self, sandbox: &Sandbox, command: &[String], in_file: &str, out_file: &str) -> (std::result::Result<(), Status>, Resources) {
+        let (result, resources) = self.execute(sandbox, command, in_file, out_file, None);
+        match result {
+            Ok(0) => (Ok(()), resources),
+            Ok(_) => (Err(Status::RuntimeError), resources),
+            Err(status) => (Err(status), resources)
+        }
+    }
+    // runs the command under the case limits, giving back the exit code of a program that kept to them
+    pub fn execute(
+        &self, sandbox: &Sandbox, command: &[String], in_file: &str, out_file: &str, err_file: Option<&str>
+    ) -> (std::result::Result<i32, Status>, Resources) {
+        let (Ok(input_fp), Ok(output_fp), Ok(stderr_fp)) = 
+            (File::open(in_file), File::create(out_file), err_file.map(File::create).transpose()) else {
+            return (Err(Status::SystemError), Resources { time: 0, wall_time: 0, memory: 0 });
+        };
+        let Some(process) = self.spawn(
+            sandbox, command, input_fp.as_raw_fd(), output_fp.as_raw_fd(), stderr_fp.as_ref().map(|fp| fp.as_raw_fd())
+        ) else {
+            return (Err(Status::SystemError), Resources { time: 0, wall_time: 0, memory: 0 });
+        };
+        let exited = wait_all(&[&process], self.effective_wall_time_limit());
+        process.finish(exited[0])
+    }
+    // the solution and the interactor talk over two pipes, under one wall clock limit for both;
+    // an error is the solution's own failure, otherwise the interactor decides as a testlib checker
+    #[allow(clippy::too_many_arguments)]
+    pub fn interact(
+        &self, sandbox: &Sandbox, command: &[String], 
+        interactor: &Case, interactor_sandbox: &Sandbox, interactor_command: &[String], err_file: &str
+    ) -> (std::result::Result<Verdict, Status>, Resources) {
+        let (Ok((solution_in, interactor_out)), Ok((interactor_in, solution_out)), Ok(stderr_fp)) = 
+            (pipe(), pipe(), File::create(err_file)) else {
+            return (Err(Status::SystemError), Resources { time: 0, wall_time: 0, memory: 0 });
+        };
+        let Some(solution) = self.spawn(sandbox, command, solution_in.as_raw_fd(), solution_out.as_raw_fd(), None) else {
+            return (Err(Status::SystemError), Resources { time: 0, wall_time: 0, memory: 0 });
+        };
+        let Some(interactor) = interactor.spawn(
+            interactor_sandbox, interactor_command, interactor_in.as_raw_fd(), interactor_out.as_raw_fd(), Some(stderr_fp.as_raw_fd())
+        ) else {
+            solution.kill();
+            let (_, resources) = solution.finish(None);
+            return (Err(Status::SystemError), resources);
+        };
+        // each side has to see the other hang up, so only the children may keep the pipes open
+        drop((solution_in, solution_out, interactor_in, interactor_out));
+        // a solution that exited in time is not to blame for an interactor that hangs afterwards
+        let exited = wait_all(&[&solution, &interactor], self.effective_wall_time_limit());
+        let (solution_exit, resources) = solution.finish(exited[0]);
+        let (interactor_exit, _) = interactor.finish(exited[1]);
+        let verdict = match (solution_exit, interactor_exit) {
+            (Err(status @ (
+                Status::TimeLimitExceeded | Status::MemoryLimitExceeded | 
+                Status::RestrictedFunction | Status::SystemError
+            )), _) => Err(status),
+            (_, Err(status)) => Ok((Status::SpecialJudgeError, 0.0, checker_failure(status))),
+            (solution_exit, Ok(exit_code)) => {
+                let stderr = std::fs::read_to_string(err_file).unwrap_or_default();
+                match CheckerProtocol::Testlib.parse(exit_code, "", &stderr) {
+                    // a wrong answer explains a solution cut off mid-conversation, but an accepted one does not
+                    (Status::Accepted | Status::PartiallyCorrect, ..) if solution_exit != Ok(0) => Err(Status::RuntimeError),
+                    verdict => Ok(verdict)
                 }
-                else {
-                    (Err(case_status), Resources {
-                        time: timer.elapsed(),
-                        memory: memory_used
-                    })
+            }
+        };
+        (verdict, resources)
+    }
+    // starts the command under the case limits, with the given descriptors as its standard streams
+    fn spawn(&self, sandbox: &Sandbox, command: &[String], stdin: RawFd, stdout: RawFd, stderr: Option<RawFd>) -> Option<Process<'_>> {
+        let args = command.iter().map(|arg| CString::new(arg.as_str())).collect::<std::result::Result<Vec<_>, _>>().ok()?;
+        if args.is_empty() {
+            return None;
+        }
+        let mut argv = args.iter().map(|arg| arg.as_ptr()).collect::<Vec<_>>();
+        argv.push(std::ptr::null());
+        // without a usable cgroup, fall back to rlimits and rusage
+        let cgroup = sandbox.cgroup_root.and_then(|root| Cgroup::new(root, self).ok());
+        // the child blocks on the go pipe until the parent has set it up,
+        // and reports a failed setup through the error pipe, which closes on exec
+        let ((go_read, go_write), (error_read, error_write)) = (pipe().ok()?, pipe().ok()?);
+        unsafe {
+            let pid = match sandbox.isolation {
+                Some(_) => libc::syscall(libc::SYS_clone, (NAMESPACES | libc::SIGCHLD) as libc::c_ulong, 0, 0, 0, 0) as libc::pid_t,
+                None => libc::fork()
+            };
+            if pid < 0 {
+                return None;
+            }
+            if pid > 0 { // parent
+                drop(go_read);
+                drop(error_write);
+                let pidfd = libc::syscall(libc::SYS_pidfd_open, pid, 0) as libc::c_int;
+                let mut byte = 0u8;
+                let ready = 
+                    pidfd >= 0 &&
+                    sandbox.isolation.as_ref().is_none_or(|isolation| isolation.map_ids(pid).is_ok()) &&
+                    cgroup.as_ref().is_none_or(|cgroup| cgroup.attach(pid).is_ok()) &&
+                    libc::write(go_write.as_raw_fd(), &byte as *const u8 as _, 1) == 1 &&
+                    libc::read(error_read.as_raw_fd(), &mut byte as *mut u8 as _, 1) == 0;
+                if !ready {
+                    if pidfd >= 0 {
+                        libc::close(pidfd);
+                    }
+                    libc::kill(pid, libc::SIGKILL);
+                    libc::waitpid(pid, std::ptr::null_mut(), 0);
+                    return None;
                 }
+                Some(Process {
+                    case: self,
+                    pid,
+                    pidfd: OwnedFd::from_raw_fd(pidfd),
+                    cgroup,
+                    timer: Timer::new(),
+                })
             }
             else {
-                let input_fp = File::open(in_file).unwrap();
-                let output_fp = File::create(out_file).unwrap();
-                libc::dup2(input_fp.as_raw_fd(), libc::STDIN_FILENO);
-                libc::dup2(output_fp.as_raw_fd(), libc::STDOUT_FILENO);
-                if self.memory_limit > 0 {
+                // nothing below may allocate: other threads could have held the allocator lock at clone time
+                let fail = || -> ! {
+                    libc::write(error_write.as_raw_fd(), b"!".as_ptr() as _, 1);
+                    libc::_exit(1)
+                };
+                let mut byte = 0u8;
+                if libc::read(go_read.as_raw_fd(), &mut byte as *mut u8 as _, 1) != 1 {
+                    fail();
+                }
+                libc::dup2(stdin, libc::STDIN_FILENO);
+                libc::dup2(stdout, libc::STDOUT_FILENO);
+                if let Some(stderr) = stderr {
+                    libc::dup2(stderr, libc::STDERR_FILENO);
+                }
+                // the server ignores SIGPIPE, and ignored signals stay ignored across exec
+                libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+                if cgroup.is_none() && self.memory_limit > 0 {
                     let memory_limit = libc::rlimit {
                         rlim_cur: self.memory_limit,
                         rlim_max: self.memory_limit,
                     };
                     libc::setrlimit(libc::RLIMIT_DATA, &memory_limit);
                 }
-                libc::ptrace(libc::PTRACE_TRACEME, 0, 0, 0);
-                libc::execl(exe_file.as_ptr() as _, exe_file.as_ptr() as _, 0);
-                unreachable!();
+                if self.cpu_time_limit > 0 {
+                    // a whole second of slack, since the verdict is decided from the measured time;
+                    // the hard limit delivers SIGKILL, which even a namespace init cannot ignore
+                    let seconds = self.cpu_time_limit.div_ceil(1000000) + 1;
+                    let cpu_limit = libc::rlimit {
+                        rlim_cur: seconds,
+                        rlim_max: seconds,
+                    };
+                    libc::setrlimit(libc::RLIMIT_CPU, &cpu_limit);
+                }
+                set_limit(libc::RLIMIT_FSIZE, self.output_limit);
+                if let Some(cpu) = sandbox.cpu {
+                    let mut cpu_set: libc::cpu_set_t = std::mem::zeroed();
+                    libc::CPU_SET(cpu, &mut cpu_set);
+                    libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &cpu_set);
+                }
+                if let Some(isolation) = &sandbox.isolation {
+                    if !isolation.enter() {
+                        fail();
+                    }
+                }
+                if !sandbox.filter.install() {
+                    fail();
+                }
+                libc::execvp(argv[0], argv.as_ptr());
+                fail()
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standard_judge_describes_first_difference() {
+        assert_eq!(standard_judge("1 2  \n3\n\n", "1 2\n3"), (Status::Accepted, "".into()));
+        assert_eq!(
+            standard_judge("1\n2 3 5\n", "1\n2 3 4\n"),
+            (Status::WrongAnswer, "Line 2, column 5 differs: expected \"2 3 4\", got \"2 3 5\".".into())
+        );
+        assert_eq!(
+            standard_judge("1\n", "1\n2\n"),
+            (Status::WrongAnswer, "Output too short: line 2 missing, expected \"2\".".into())
+        );
+        assert_eq!(
+            standard_judge("1\n2\n", "1\n"),
+            (Status::WrongAnswer, "Extra output on line 2: \"2\".".into())
+        );
+    }
+
+    #[test]
+    fn strict_judge_shows_invisible_differences() {
+        assert_eq!(strict_judge("1\n", "1\n").0, Status::Accepted);
+        assert_eq!(
+            strict_judge("1\r\n", "1\n"),
+            (Status::WrongAnswer, "Line 1, column 2 differs: expected \"1\", got \"1\\r\".".into())
+        );
+    }
+
+    #[test]
+    fn excerpt_is_truncated_around_column() {
+        let line = "0123456789".repeat(10);
+        assert_eq!(excerpt(&line, 50), format!("{:?}", format!("...{}...", &line[30..70])));
+        assert_eq!(excerpt("short", 0), "\"short\"");
+    }
+
+    #[test]
+    fn token_judge_ignores_whitespace() {
+        assert_eq!(token_judge("1  2\n3\n\n", "1 2 3"), Status::Accepted);
+        assert_eq!(token_judge("1\t2\r\n3", "1 2\n3\n"), Status::Accepted);
+        assert_eq!(token_judge("1 2", "1 2 3"), Status::WrongAnswer);
+        assert_eq!(token_judge("12 3", "1 23"), Status::WrongAnswer);
+        assert_eq!(token_judge("", "\n"), Status::Accepted);
+    }
+
+    #[test]
+    fn case_insensitive_judge_ignores_case_only() {
+        assert_eq!(case_insensitive_judge("YES\nno\n", "yes\nNO"), Status::Accepted);
+        assert_eq!(case_insensitive_judge("Yes  \n", "yes"), Status::Accepted);
+        assert_eq!(case_insensitive_judge("y e s", "yes"), Status::WrongAnswer);
+        assert_eq!(case_insensitive_judge("yes\nno", "yes"), Status::WrongAnswer);
+    }
+
+    #[test]
+    fn unordered_lines_judge_ignores_order() {
+        assert_eq!(unordered_lines_judge("3 4\n1 2\n", "1 2\n3 4"), Status::Accepted);
+        assert_eq!(unordered_lines_judge("b \na\n\n", "a\nb"), Status::Accepted);
+        assert_eq!(unordered_lines_judge("a\na\n", "a\nb\n"), Status::WrongAnswer);
+        assert_eq!(unordered_lines_judge("a\n", "a\na\n"), Status::WrongAnswer);
+        assert_eq!(unordered_lines_judge("1  2\n", "1 2\n"), Status::WrongAnswer);
+    }
+
+    #[test]
+    fn float_judge_uses_both_epsilons() {
+        assert_eq!(float_judge("0.3333334", "0.333333333", 1e-6, 0.0).0, Status::Accepted);
+        assert_eq!(float_judge("1000001", "1000000", 0.0, 1e-6).0, Status::Accepted);
+        assert_eq!(float_judge("0.334", "0.333333333", 1e-6, 1e-6).0, Status::WrongAnswer);
+        assert_eq!(float_judge("1.0 abc", "1 abc", 1e-9, 0.0).0, Status::Accepted);
+        assert_eq!(float_judge("nan", "1", 1.0, 1.0).0, Status::WrongAnswer);
+    }
+
+    #[test]
+    fn float_judge_points_at_first_mismatch() {
+        assert_eq!(
+            float_judge("1 2\n3.5", "1 2\n3", 0.1, 0.0),
+            (Status::WrongAnswer, "Token 3 on line 2: expected 3, got 3.5.".into())
+        );
+        assert_eq!(
+            float_judge("1", "1 2", 0.1, 0.0),
+            (Status::WrongAnswer, "Token 2: expected 2, got end of output.".into())
+        );
+        assert_eq!(
+            float_judge("1 2\n\n7", "1 2", 0.1, 0.0),
+            (Status::WrongAnswer, "Token 3 on line 3: expected end of output, got 7.".into())
+        );
+    }
+
+    #[test]
+    fn testlib_exit_codes_give_verdicts() {
+        let testlib = CheckerProtocol::Testlib;
+        assert_eq!(testlib.parse(0, "", "ok 1 number\n"), (Status::Accepted, 1.0, "ok 1 number".into()));
+        assert_eq!(testlib.parse(1, "", "wrong answer 1st differs\n"), (Status::WrongAnswer, 0.0, "wrong answer 1st differs".into()));
+        assert_eq!(testlib.parse(2, "", "wrong output format\n"), (Status::WrongAnswer, 0.0, "wrong output format".into()));
+    }
+
+    #[test]
+    fn testlib_failure_is_checker_error() {
+        let testlib = CheckerProtocol::Testlib;
+        assert_eq!(testlib.parse(3, "", "FAIL answer missing\n"), (Status::SpecialJudgeError, 0.0, "FAIL answer missing".into()));
+        assert_eq!(testlib.parse(-1, "", ""), (Status::SpecialJudgeError, 0.0, "".into()));
+    }
+
+    #[test]
+    fn testlib_points_give_fraction() {
+        let testlib = CheckerProtocol::Testlib;
+        assert_eq!(testlib.parse(7, "", "points 0.25 two of eight\n"), (Status::PartiallyCorrect, 0.25, "two of eight".into()));
+        assert_eq!(testlib.parse(7, "", "points 1\n"), (Status::Accepted, 1.0, "".into()));
+        assert_eq!(testlib.parse(7, "", "0 nothing"), (Status::WrongAnswer, 0.0, "nothing".into()));
+        assert_eq!(testlib.parse(7, "", "points 1.5 too many"), (Status::SpecialJudgeError, 0.0, "points 1.5 too many".into()));
+        assert_eq!(testlib.parse(7, "", "points half"), (Status::SpecialJudgeError, 0.0, "points half".into()));
+    }
+
+    #[test]
+    fn native_lines_give_verdicts() {
+        let native = CheckerProtocol::Native;
+        assert_eq!(native.parse(0, "Accepted\nfine\n", ""), (Status::Accepted, 1.0, "fine".into()));
+        assert_eq!(native.parse(0, "Wrong Answer\noff by one\n", ""), (Status::WrongAnswer, 1.0, "off by one".into()));
+        assert_eq!(native.parse(0, "Partially Correct\nhalf\n0.5\n", ""), (Status::PartiallyCorrect, 0.5, "half".into()));
+        assert_eq!(native.parse(0, "Accepted\nall\n1\n", ""), (Status::Accepted, 1.0, "all".into()));
+        assert_eq!(native.parse(0, "Partially Correct\nno fraction\n", "").0, Status::SpecialJudgeError);
+        assert_eq!(native.parse(0, "Wrong Answer\nno\n0.5\n", "").0, Status::SpecialJudgeError);
+        assert_eq!(native.parse(0, "Accepted\n", "").0, Status::SpecialJudgeError);
+        assert_eq!(native.parse(0, "Great\nfine\n", "").0, Status::SpecialJudgeError);
+    }
+}
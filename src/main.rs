@@ -9,9 +9,13 @@ use std::{fs::File, sync::Arc};
 use server::*;
 
 mod server;
+mod cgroup;
 mod database;
+mod events;
+mod isolation;
 mod judge;
 mod models;
+mod seccomp;
 mod serde_helper;
 mod tokens;
 mod worker;
@@ -45,7 +49,9 @@ async fn main() -> std::io::Result<()> {
             .service(exit)
             .service(post_jobs)
             .service(get_jobs)
+            .service(get_all_job_events)
             .service(get_job_by_id)
+            .service(get_job_events)
             .service(rerun_job)
             .service(cancel_job)
             .service(get_users)
@@ -55,8 +61,11 @@ async fn main() -> std::io::Result<()> {
             .service(get_contests)
             .service(get_contest_by_id)
             .service(get_contest_ranklist)
+            .service(resolve_contest)
+            .service(get_contest_stats)
             .service(get_contest_problems)
             .service(get_problem_by_id)
+            .service(get_problem_stats)
             .service(Files::new("/", "./frontend/.output/public").index_file("index.html"))
     })
     .bind((
@@ -1,4 +1,6 @@
 use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
 
 use serde::{Serialize, Deserialize};
 use chrono::{DateTime, Local, Utc};
@@ -10,7 +12,13 @@ pub enum ProblemType {
     Strict,
     #[serde(rename = "spj")]
     SpecialJudge,
-    DynamicRanking
+    DynamicRanking,
+    Float,
+    Token,
+    CaseInsensitive,
+    UnorderedLines,
+    Interactive,
+    OutputOnly
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -21,11 +29,108 @@ pub enum MiscType {
     Packed {
         packing: Vec<Vec<i32>>
     },
+    Subtasks {
+        subtasks: Vec<Subtask>
+    },
     SpecialJudge {
-        special_judge: Vec<String>
+        special_judge: Vec<String>,
+        #[serde(default)]
+        checker_protocol: CheckerProtocol,
+        #[serde(default)]
+        checker_limits: CheckerLimits
     },
     DynamicRanking {
-        dynamic_ranking_ratio: i32
+        dynamic_ranking_ratio: f64
+    },
+    Float {
+        #[serde(default)]
+        absolute_epsilon: f64,
+        #[serde(default)]
+        relative_epsilon: f64
+    },
+    // the interactor reads the solution's output on stdin, writes its input on stdout,
+    // and gives the verdict by testlib exit codes with the info on stderr
+    Interactive {
+        interactor: Vec<String>,
+        #[serde(default)]
+        interactor_limits: CheckerLimits
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SubtaskPolicy {
+    // every case is run, and the subtask earns the weighted share of its cases
+    Sum,
+    // the subtask earns the worst case's share, and stops at the first case earning nothing
+    MinRatio,
+    // every case has to be accepted, and the first that is not stops the subtask and zeroes it
+    #[default]
+    AllOrNothing
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Subtask {
+    // 1-based, like the packing
+    pub cases: Vec<i32>,
+    // the sum of the case scores by default, which also weigh the cases
+    #[serde(default)]
+    pub score: Option<f32>,
+    #[serde(default)]
+    pub policy: SubtaskPolicy,
+    // 1-based indices of earlier subtasks that must earn their full score
+    #[serde(default)]
+    pub dependencies: Vec<usize>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckerProtocol {
+    // a status name on the first line of stdout, the info on the second,
+    // and optionally the fraction of the case score earned on the third
+    #[default]
+    Native,
+    // testlib.h exit codes, with `input output answer` appended to the command
+    Testlib
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct CheckerLimits {
+    pub cpu_time_limit: u64,
+    pub wall_time_limit: u64,
+    pub memory_limit: u64,
+    // a checker that runs other programs or reads files its command does not name has to be let in here
+    pub syscalls: SyscallPolicy,
+    pub bind_mounts: Vec<BindMount>,
+}
+
+impl Default for CheckerLimits {
+    fn default() -> Self {
+        Self {
+            cpu_time_limit: 10000000,
+            wall_time_limit: 0,
+            memory_limit: 512 << 20,
+            syscalls: SyscallPolicy::default(),
+            bind_mounts: vec![],
+        }
+    }
+}
+
+impl CheckerLimits {
+    // the checker is judged like a solution on a case with these limits
+    pub fn as_case(&self) -> Case {
+        Case {
+            score: 0.0,
+            input_file: "".into(),
+            answer_file: "".into(),
+            cpu_time_limit: self.cpu_time_limit,
+            wall_time_limit: self.wall_time_limit,
+            memory_limit: self.memory_limit,
+            process_limit: 0,
+            cpu_limit: default_cpu_limit(),
+            output_limit: 0,
+        }
     }
 }
 
@@ -39,7 +144,107 @@ pub struct Problem {
     #[serde(default)]
     pub description: String,
     pub misc: MiscType,
-    pub cases: Vec<Case>
+    pub cases: Vec<Case>,
+    // the built-in checkers quote the answer when explaining a wrong one
+    #[serde(default)]
+    pub hide_diff: bool,
+    // keyed by language name, for problems where the submission only implements functions
+    #[serde(default)]
+    pub extra_files: HashMap<String, ExtraFiles>,
+    // the checker uploads of an output-only problem are judged with
+    #[serde(default)]
+    pub checker: Option<ProblemType>,
+}
+
+// copied next to the submission; only the sources are given to the compiler, in place of %EXTRA%
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ExtraFiles {
+    #[serde(default)]
+    pub sources: Vec<String>,
+    #[serde(default)]
+    pub headers: Vec<String>,
+}
+
+impl Problem {
+    pub fn dynamic_ranking_ratio(&self) -> Option<f64> {
+        match (self.problem_type, &self.misc) {
+            (ProblemType::DynamicRanking, MiscType::DynamicRanking { dynamic_ranking_ratio }) => {
+                Some(*dynamic_ranking_ratio)
+            }
+            _ => None
+        }
+    }
+    // a packing is a list of all-or-nothing subtasks
+    pub fn subtasks(&self) -> Option<Vec<Subtask>> {
+        match &self.misc {
+            MiscType::Packed { packing } => Some(packing.iter().map(|cases| Subtask {
+                cases: cases.clone(),
+                score: None,
+                policy: SubtaskPolicy::AllOrNothing,
+                dependencies: vec![]
+            }).collect()),
+            MiscType::Subtasks { subtasks } => Some(subtasks.clone()),
+            _ => None
+        }
+    }
+    // without a checker of its own, an output-only problem goes by its misc, or compares like a standard one
+    pub fn checker(&self) -> ProblemType {
+        match (self.problem_type, self.checker, &self.misc) {
+            (ProblemType::OutputOnly, Some(checker), _) => checker,
+            (ProblemType::OutputOnly, None, MiscType::SpecialJudge { .. }) => ProblemType::SpecialJudge,
+            (ProblemType::OutputOnly, None, MiscType::Float { .. }) => ProblemType::Float,
+            (ProblemType::OutputOnly, None, _) => ProblemType::Standard,
+            (problem_type, ..) => problem_type
+        }
+    }
+    // checked when the config is loaded, as judging relies on the subtask indices, the checker and the extra files
+    pub fn validate(&self, languages: &[Language]) -> Result<()> {
+        // an extra file named like the submission would silently take its place
+        for language in languages {
+            let Some(extra_files) = self.extra_files.get(&language.name) else { continue };
+            if let Some(file) = extra_files.sources.iter().chain(&extra_files.headers).find(|file| {
+                Path::new(file).file_name().is_some_and(|name| *name == *language.file_name)
+            }) {
+                return Err(ERR_INVALID_ARGUMENT.clone().with_message(
+                    format!("Problem {} has extra file {}, which is where {} submissions go.", self.id, file, language.name)
+                ));
+            }
+        }
+        match (self.problem_type, self.checker) {
+            (_, None) => {}
+            (ProblemType::OutputOnly, Some(ProblemType::Interactive | ProblemType::OutputOnly | ProblemType::DynamicRanking)) => {
+                return Err(ERR_INVALID_ARGUMENT.clone().with_message(
+                    format!("Problem {} cannot check its outputs that way.", self.id)
+                ));
+            }
+            (ProblemType::OutputOnly, Some(_)) => {}
+            (_, Some(_)) => {
+                return Err(ERR_INVALID_ARGUMENT.clone().with_message(
+                    format!("Problem {} is not output-only, so its type is its checker.", self.id)
+                ));
+            }
+        }
+        for (k, subtask) in self.subtasks().unwrap_or_default().iter().enumerate() {
+            if let Some(case) = subtask.cases.iter().find(|case| **case < 1 || **case as usize > self.cases.len()) {
+                return Err(ERR_INVALID_ARGUMENT.clone().with_message(
+                    format!("Problem {} subtask {} has no case {}.", self.id, k + 1, case)
+                ));
+            }
+            if let Some(dependency) = subtask.dependencies.iter().find(|dependency| **dependency < 1 || **dependency > k) {
+                return Err(ERR_INVALID_ARGUMENT.clone().with_message(
+                    format!("Problem {} subtask {} depends on subtask {}, which does not come before it.", self.id, k + 1, dependency)
+                ));
+            }
+        }
+        Ok(())
+    }
+    // an empty misc parses as none rather than as float, and like a misc giving no epsilons, it compares exactly
+    pub fn float_epsilon(&self) -> (f64, f64) {
+        match &self.misc {
+            MiscType::Float { absolute_epsilon, relative_epsilon } => (*absolute_epsilon, *relative_epsilon),
+            _ => (0.0, 0.0)
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -47,33 +252,177 @@ pub struct Case {
     pub score: f32,
     pub input_file: String,
     pub answer_file: String,
+    #[serde(alias = "time_limit")]
+    pub cpu_time_limit: u64,
+    #[serde(default)]
+    pub wall_time_limit: u64,
+    pub memory_limit: u64,
+    #[serde(default)]
+    pub process_limit: u64,
+    #[serde(default = "default_cpu_limit")]
+    pub cpu_limit: f64,
+    // only compilation bounds the files a program writes
+    #[serde(skip)]
+    pub output_limit: u64,
+}
+
+fn default_cpu_limit() -> f64 {
+    1.0
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SyscallAction {
+    #[default]
+    Allow,
+    Deny
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SyscallPolicy {
+    #[serde(default)]
+    pub default_action: SyscallAction,
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+impl Default for SyscallPolicy {
+    fn default() -> Self {
+        Self {
+            default_action: SyscallAction::Allow,
+            allow: vec![],
+            deny: [
+                "clone3", "fork", "vfork", "ptrace", "socket",
+                "mount", "umount2", "pivot_root", "chroot", "unshare", "setns"
+            ].map(String::from).to_vec()
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BindMount {
+    pub source: String,
+    pub target: Option<String>,
+    #[serde(default)]
+    pub writable: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct CompileLimits {
     pub time_limit: u64,
-    pub memory_limit: u64
+    pub memory_limit: u64,
+    pub output_limit: u64,
+    pub message_limit: usize,
+    // compilers run their own passes and linkers, so unlike a run, compilation may fork
+    pub syscalls: SyscallPolicy,
+}
+
+impl Default for CompileLimits {
+    fn default() -> Self {
+        Self {
+            time_limit: 10000000,
+            memory_limit: 1 << 30,
+            output_limit: 64 << 20,
+            message_limit: 4096,
+            syscalls: SyscallPolicy {
+                default_action: SyscallAction::Allow,
+                allow: vec![],
+                deny: [
+                    "ptrace", "socket", "mount", "umount2", "pivot_root", "chroot", "unshare", "setns"
+                ].map(String::from).to_vec()
+            },
+        }
+    }
+}
+
+impl CompileLimits {
+    // the compiler is run like a solution on a case with these limits, all its processes together
+    pub fn as_case(&self) -> Case {
+        Case {
+            score: 0.0,
+            input_file: "".into(),
+            answer_file: "".into(),
+            cpu_time_limit: self.time_limit,
+            wall_time_limit: self.time_limit,
+            memory_limit: self.memory_limit,
+            process_limit: 0,
+            cpu_limit: default_cpu_limit(),
+            output_limit: self.output_limit,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Language {
     pub name: String,
     pub file_name: String,
+    // an empty compile command means the source is run as is
+    #[serde(default)]
     pub command: Vec<String>,
+    #[serde(default)]
+    pub run_command: Option<Vec<String>>,
+    #[serde(default)]
+    pub compile_limits: CompileLimits,
+    #[serde(default)]
+    pub syscalls: SyscallPolicy,
+    #[serde(default)]
+    pub rootfs: Option<String>,
+    #[serde(default)]
+    pub bind_mounts: Vec<BindMount>,
 }
 
 impl Language {
-    pub fn expand_command(&self, input: &str, output: &str) -> Vec<String> {
-        self.command.iter().map(|segment| { 
+    pub fn expand_command(&self, input: &str, extra: &[String], output: &str) -> Vec<String> {
+        self.command.iter().flat_map(|segment| { 
             match segment.as_str() {
-                "%INPUT%" => input.into(),
-                "%OUTPUT%" => output.into(),
-                _ => segment.clone()
+                "%INPUT%" => vec![input.into()],
+                "%EXTRA%" => extra.to_vec(),
+                "%OUTPUT%" => vec![output.into()],
+                _ => vec![segment.clone()]
             }
         }).collect()
     }
+    pub fn expand_run_command(&self, executable: &str, source: &str, dir: &str) -> Vec<String> {
+        match &self.run_command {
+            // placeholders may be part of a segment, as in `%DIR%/main.py`
+            Some(run_command) => run_command.iter().map(|segment| {
+                segment
+                    .replace("%EXECUTABLE%", executable)
+                    .replace("%SOURCE%", source)
+                    .replace("%DIR%", dir)
+            }).collect(),
+            None => vec![executable.into()]
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ServerConfig {
     pub bind_address: String,
     pub bind_port: u16,
+    #[serde(default = "default_cgroup_root")]
+    pub cgroup_root: Option<String>,
+    #[serde(default = "default_isolation")]
+    pub isolation: bool,
+    #[serde(default = "default_judge_threads")]
+    pub judge_threads: usize,
+    #[serde(default)]
+    pub pin_cpus: bool,
+}
+
+fn default_judge_threads() -> usize {
+    1
+}
+
+fn default_isolation() -> bool {
+    true
+}
+
+fn default_cgroup_root() -> Option<String> {
+    Some("/sys/fs/cgroup/oj".into())
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -91,7 +440,7 @@ pub enum JobStatus {
     Canceled,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
 pub enum Status {
     Waiting,
     Running,
@@ -112,16 +461,26 @@ pub enum Status {
     SystemError,
     #[serde(rename = "SPJ Error")]
     SpecialJudgeError,
+    #[serde(rename = "Restricted Function")]
+    RestrictedFunction,
+    #[serde(rename = "Partially Correct")]
+    PartiallyCorrect,
     Skipped
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct JobRequest {
+    #[serde(default)]
     pub source_code: String,
+    #[serde(default)]
     pub language: String,
     pub user_id: i32,
     pub contest_id: i32,
     pub problem_id: i32,
+    // one uploaded output per case, for output-only problems
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -129,6 +488,8 @@ pub struct JobCase {
     pub id: i32,
     pub result: Status,
     pub time: u64,
+    #[serde(default)]
+    pub wall_time: u64,
     pub memory: u64,
     pub info: String,
 }
@@ -143,6 +504,15 @@ pub struct Job {
     pub result: Status,
     pub score: f32,
     pub cases: Vec<JobCase>,
+    #[serde(default)]
+    pub subtasks: Vec<JobSubtask>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct JobSubtask {
+    pub id: i32,
+    pub result: Status,
+    pub score: f32,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -163,14 +533,35 @@ pub struct Contest {
     pub to: DateTime<Utc>,
     pub problem_ids: Vec<i32>,
     pub user_ids: Vec<i32>,
-    pub submission_limit: i32
+    pub submission_limit: i32,
+    // submissions after the end are still judged, but do not count for the contest
+    #[serde(default)]
+    pub practice_after_end: bool,
+    // the results of later submissions stay hidden from the ranklist until resolved
+    #[serde(default)]
+    #[serde(serialize_with = "crate::serde_helper::serialize_optional_datetime")]
+    #[serde(deserialize_with = "crate::serde_helper::deserialize_optional_datetime")]
+    pub freeze_at: Option<DateTime<Utc>>,
+    // (user id, problem id) pairs whose frozen submissions have been revealed
+    #[serde(default)]
+    pub resolved: Vec<(i32, i32)>
 }
 
-#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+impl Contest {
+    pub fn hides(&self, job: &Job) -> bool {
+        self.freeze_at.is_some_and(|freeze_at| job.created_time >= freeze_at) &&
+        !self.resolved.contains(&(job.submission.user_id, job.submission.problem_id))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
 #[serde(rename_all = "snake_case")]
 pub enum ScoringRule {
+    #[default]
     Latest,
-    Highest
+    Highest,
+    // by problems solved, then by penalty time
+    Icpc
 }
 
 impl ScoringRule {
@@ -185,13 +576,58 @@ impl ScoringRule {
                     .then(a.created_time.cmp(&b.created_time).reverse())
                 })
             }
+            Self::Icpc => {
+                jobs.iter().filter(|job| job.result == Status::Accepted).min_by_key(|job| job.created_time)
+                    .or_else(|| jobs.iter().max_by_key(|job| job.created_time))
+            }
         }
     } 
 }
 
-impl Default for ScoringRule {
-    fn default() -> Self {
-        Self::Latest
+const PENALTY_MINUTES: i64 = 20;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ProblemAttempts {
+    // counted up to and including the first accepted one
+    pub attempts: i32,
+    // minutes from the start of the contest
+    pub solved_at: Option<i64>,
+}
+
+impl ProblemAttempts {
+    // compilation errors are not counted as attempts, nor is anything from before the start
+    pub fn new(jobs: &[Job], start: DateTime<Utc>) -> Self {
+        let mut jobs = jobs.iter()
+            .filter(|job| job.result != Status::CompilationError && job.created_time >= start)
+            .collect::<Vec<_>>();
+        jobs.sort_by_key(|job| job.created_time);
+        match jobs.iter().position(|job| job.result == Status::Accepted) {
+            Some(i) => Self {
+                attempts: i as i32 + 1,
+                solved_at: Some((jobs[i].created_time - start).num_minutes())
+            },
+            None => Self {
+                attempts: jobs.len() as i32,
+                solved_at: None
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct IcpcStanding {
+    pub solved: i32,
+    pub penalty: i64,
+    pub problems: Vec<ProblemAttempts>,
+}
+
+impl IcpcStanding {
+    pub fn new(problems: Vec<ProblemAttempts>) -> Self {
+        let solved = problems.iter().filter(|problem| problem.solved_at.is_some()).count() as i32;
+        let penalty = problems.iter().filter_map(|problem| {
+            problem.solved_at.map(|minutes| minutes + PENALTY_MINUTES * (problem.attempts - 1) as i64)
+        }).sum();
+        Self { solved, penalty, problems }
     }
 }
 
@@ -209,14 +645,14 @@ impl TieBreaker {
             Self::SubmissionTime => {
                 let a_time = a.jobs.iter().map(|job| {
                     match job { 
-                        Some(job) => job.created_time.clone(),
+                        Some(job) => job.created_time,
                         None => DateTime::<Utc>::MAX_UTC
                     }
                 }).min().unwrap();
                 
                 let b_time = b.jobs.iter().map(|job| {
                     match job { 
-                        Some(job) => job.created_time.clone(),
+                        Some(job) => job.created_time,
                         None => DateTime::<Utc>::MAX_UTC
                     }
                 }).min().unwrap();
@@ -242,25 +678,104 @@ pub struct Ranking {
     pub user: User,
     pub rank: i32,
     pub scores: Vec<f32>,
+    pub results: Vec<Option<Status>>,
+    #[serde(flatten)]
+    pub icpc: Option<IcpcStanding>,
+    // per problem, the submissions hidden by a freeze
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pending: Vec<i32>,
 }
 
 #[derive(Debug)]
 pub(crate) struct RankHelper {
     pub user: User,
     pub jobs: Vec<Option<Job>>,
-    pub job_counts: Vec<i32>
+    pub job_counts: Vec<i32>,
+    pub icpc: Option<IcpcStanding>,
+    pub pending: Vec<i32>
 }
 
 impl RankHelper {
     pub fn score(&self) -> f32 {
         let mut score = 0f32;
-        for job in &self.jobs {
-            if let Some(job) = job {
-                score += job.score;
-            }
+        for job in self.jobs.iter().flatten() {
+            score += job.score;
         }
         score
     }
+    // the better one comes first, before any tie breaker
+    pub fn compare(&self, other: &RankHelper) -> Ordering {
+        match (&self.icpc, &other.icpc) {
+            (Some(a), Some(b)) => Ord::cmp(&b.solved, &a.solved).then(Ord::cmp(&a.penalty, &b.penalty)),
+            _ => f32::total_cmp(&self.score(), &other.score()).reverse()
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FirstSolve {
+    pub user_id: i32,
+    pub job_id: i32,
+    pub time: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScoreCount {
+    pub score: f32,
+    pub users: i32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ProblemStats {
+    pub problem_id: i32,
+    pub attempts: i32,
+    pub accepted: i32,
+    pub acceptance_rate: f32,
+    pub first_solve: Option<FirstSolve>,
+    // of the best score of each user who tried
+    pub score_distribution: Vec<ScoreCount>,
+    pub by_status: BTreeMap<Status, i32>,
+    pub by_language: BTreeMap<String, BTreeMap<Status, i32>>,
+}
+
+impl ProblemStats {
+    // from the finished jobs on one problem
+    pub fn new(problem_id: i32, jobs: &[Job]) -> Self {
+        let accepted = jobs.iter().filter(|job| job.result == Status::Accepted).collect::<Vec<_>>();
+        let mut best_scores: BTreeMap<i32, f32> = BTreeMap::new();
+        let mut by_status = BTreeMap::new();
+        let mut by_language: BTreeMap<String, BTreeMap<Status, i32>> = BTreeMap::new();
+        for job in jobs {
+            let best = best_scores.entry(job.submission.user_id).or_insert(job.score);
+            *best = best.max(job.score);
+            *by_status.entry(job.result).or_insert(0) += 1;
+            *by_language.entry(job.submission.language.clone()).or_default().entry(job.result).or_insert(0) += 1;
+        }
+        let mut score_distribution: Vec<ScoreCount> = vec![];
+        let mut scores = best_scores.into_values().collect::<Vec<_>>();
+        scores.sort_by(f32::total_cmp);
+        for score in scores {
+            match score_distribution.last_mut() {
+                Some(count) if count.score == score => count.users += 1,
+                _ => score_distribution.push(ScoreCount { score, users: 1 })
+            }
+        }
+        Self {
+            problem_id,
+            attempts: jobs.len() as i32,
+            accepted: accepted.len() as i32,
+            acceptance_rate: if jobs.is_empty() { 0.0 } else { accepted.len() as f32 / jobs.len() as f32 },
+            first_solve: accepted.iter().min_by_key(|job| (job.created_time, job.id)).map(|job| FirstSolve {
+                user_id: job.submission.user_id,
+                job_id: job.id,
+                time: job.created_time
+            }),
+            score_distribution,
+            by_status,
+            by_language,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -371,6 +886,75 @@ lazy_static::lazy_static! {
         http_status: 500,
     };
 
+    pub static ref ERR_CONTEST_CLOSED: Error = Error {
+        code: 7,
+        reason: "ERR_CONTEST_CLOSED",
+        message: "".into(),
+        http_status: 403,
+    };
+
 }
 
-pub type Result<T> = std::result::Result<T, Error>;
\ No newline at end of file
+pub type Result<T> = std::result::Result<T, Error>;
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::*;
+
+    fn job_at(start: DateTime<Utc>, minutes: i64, result: Status) -> Job {
+        Job {
+            id: 0,
+            created_time: start + TimeDelta::minutes(minutes),
+            updated_time: start + TimeDelta::minutes(minutes),
+            submission: JobRequest {
+                source_code: "".into(),
+                language: "".into(),
+                user_id: 0,
+                contest_id: 1,
+                problem_id: 0,
+                outputs: vec![]
+            },
+            state: JobStatus::Finished,
+            result,
+            score: 0.0,
+            cases: vec![],
+            subtasks: vec![]
+        }
+    }
+
+    #[test]
+    fn problem_attempts_stop_at_first_accepted() {
+        let start = Utc::now();
+        let jobs = [
+            job_at(start, 50, Status::Accepted),
+            job_at(start, 3, Status::WrongAnswer),
+            job_at(start, 10, Status::CompilationError),
+            job_at(start, 42, Status::TimeLimitExceeded),
+            job_at(start, 61, Status::WrongAnswer),
+        ];
+        let attempts = ProblemAttempts::new(&jobs, start);
+        assert_eq!((attempts.attempts, attempts.solved_at), (3, Some(50)));
+        let attempts = ProblemAttempts::new(&jobs[1..2], start);
+        assert_eq!((attempts.attempts, attempts.solved_at), (1, None));
+    }
+
+    #[test]
+    fn problem_attempts_ignore_jobs_before_start() {
+        let start = Utc::now();
+        let jobs = [job_at(start, -30, Status::Accepted), job_at(start, 5, Status::Accepted)];
+        let attempts = ProblemAttempts::new(&jobs, start);
+        assert_eq!((attempts.attempts, attempts.solved_at), (1, Some(5)));
+    }
+
+    #[test]
+    fn icpc_penalty_adds_rejected_attempts() {
+        let standing = IcpcStanding::new(vec![
+            ProblemAttempts { attempts: 3, solved_at: Some(50) },
+            ProblemAttempts { attempts: 4, solved_at: None },
+            ProblemAttempts { attempts: 1, solved_at: Some(7) },
+        ]);
+        assert_eq!(standing.solved, 2);
+        assert_eq!(standing.penalty, 50 + 2 * PENALTY_MINUTES + 7);
+    }
+}
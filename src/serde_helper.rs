@@ -2,6 +2,8 @@ use chrono::{DateTime, Utc, NaiveDateTime, TimeZone};
 
 struct DateTimeVisitor;
 
+struct OptionalDateTimeVisitor;
+
 impl<'de> serde::de::Visitor<'de> for DateTimeVisitor {
     type Value = DateTime<Utc>;
     fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -15,6 +17,28 @@ impl<'de> serde::de::Visitor<'de> for DateTimeVisitor {
     }
 }
 
+impl<'de> serde::de::Visitor<'de> for OptionalDateTimeVisitor {
+    type Value = Option<DateTime<Utc>>;
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a datetime or null")
+    }
+    fn visit_none<E>(self) -> std::result::Result<Self::Value, E>
+        where
+            E: serde::de::Error, {
+        Ok(None)
+    }
+    fn visit_unit<E>(self) -> std::result::Result<Self::Value, E>
+        where
+            E: serde::de::Error, {
+        Ok(None)
+    }
+    fn visit_some<D>(self, deserializer: D) -> std::result::Result<Self::Value, D::Error>
+        where
+            D: serde::Deserializer<'de>, {
+        deserialize_datetime(deserializer).map(Some)
+    }
+}
+
 pub fn serialize_datetime<S>(value: &DateTime<Utc>, serializer: S) -> std::result::Result<S::Ok, S::Error> 
     where S: serde::Serializer 
 {
@@ -26,4 +50,19 @@ pub fn deserialize_datetime<'de, D>(deserializer: D) -> std::result::Result<Date
     where D: serde::Deserializer<'de>
 {
     deserializer.deserialize_str(DateTimeVisitor)
-}
\ No newline at end of file
+}
+
+pub fn serialize_optional_datetime<S>(value: &Option<DateTime<Utc>>, serializer: S) -> std::result::Result<S::Ok, S::Error> 
+    where S: serde::Serializer 
+{
+    match value {
+        Some(value) => serialize_datetime(value, serializer),
+        None => serializer.serialize_none()
+    }
+}
+
+pub fn deserialize_optional_datetime<'de, D>(deserializer: D) -> std::result::Result<Option<DateTime<Utc>>, D::Error>
+    where D: serde::Deserializer<'de>
+{
+    deserializer.deserialize_option(OptionalDateTimeVisitor)
+}
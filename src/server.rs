@@ -6,6 +6,8 @@ use http::StatusCode;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::sync::{Arc, Mutex};
+use crate::database::JobIndex;
+use crate::events::EventStream;
 use crate::models::*;
 use crate::tokens::{Token, TokenFactory};
 use crate::worker::Worker;
@@ -77,8 +79,13 @@ pub async fn post_jobs(req: HttpRequest, request: web::Json<JobRequest>) -> impl
     }
     let job = job.unwrap();
     let response = HttpResponse::Ok().json(&job);
-    let job = Arc::new(Mutex::new(job));
-    worker.push_job(Arc::clone(&job));
+    if let Err(e) = worker.push_job(Arc::new(Mutex::new(job))) {
+        return HttpResponse::build(StatusCode::from_u16(e.http_status).unwrap()).json(json!({
+            "code": e.code,
+            "reason": e.reason,
+            "message": e.message
+        }));
+    }
     response
 }
 
@@ -92,24 +99,45 @@ struct JobQuery {
     from: Option<chrono::DateTime<Utc>>,
     to: Option<chrono::DateTime<Utc>>,
     state: Option<JobStatus>,
-    result: Option<Status>
+    result: Option<Status>,
+    // only administrators may see through a freeze
+    #[serde(default)]
+    unfrozen: bool
 }
 
 
 #[get("/jobs")]
 pub async fn get_jobs(req: HttpRequest, query: web::Query<JobQuery>) -> impl Responder {
     let worker = req.app_data::<Arc<Worker>>().unwrap();
-    match worker.find_jobs(move |job| {
+    let named_user_id = match query.user_name {
+        Some(ref user_name) => match worker.database().find_user_by_name(user_name) {
+            Ok(Some(user)) => Some(user.id),
+            Ok(None) => return HttpResponse::Ok().json(Vec::<Job>::new()),
+            Err(e) => return HttpResponse::build(StatusCode::from_u16(e.http_status).unwrap()).json(json!({
+                "code": e.code,
+                "reason": e.reason,
+                "message": e.message
+            }))
+        },
+        None => None
+    };
+    let frozen = !(query.unfrozen && require_admin(&req).is_ok());
+    let result = query.result;
+    // the narrowest index the filters allow, the predicate still checks all of them
+    let index = match (query.user_id.or(named_user_id), query.problem_id, query.contest_id) {
+        (Some(user_id), Some(problem_id), _) => JobIndex::UserProblem(user_id, problem_id),
+        (Some(user_id), None, _) => JobIndex::User(user_id),
+        (_, _, Some(contest_id)) => JobIndex::Contest(contest_id),
+        (_, Some(problem_id), _) => JobIndex::Problem(problem_id),
+        _ if query.from.is_some() || query.to.is_some() => JobIndex::CreatedTime(query.from, query.to),
+        _ => JobIndex::All
+    };
+    match worker.find_jobs_in(index, move |job| {
         if let Some(user_id) = query.user_id {
             if job.submission.user_id != user_id { return Ok(false); }
         }
-        if let Some(ref user_name) = query.user_name {
-            match worker.database().find_user_by_name(user_name)? {
-                Some(user) => {
-                    if job.submission.user_id != user.id { return Ok(false); }
-                }
-                None => { return Ok(false); }
-            }
+        if let Some(user_id) = named_user_id {
+            if job.submission.user_id != user_id { return Ok(false); }
         }
         if let Some(contest_id) = query.contest_id {
             if job.submission.contest_id != contest_id { return Ok(false); }
@@ -129,10 +157,14 @@ pub async fn get_jobs(req: HttpRequest, query: web::Query<JobQuery>) -> impl Res
         if let Some(state) = query.state {
             if job.state != state { return Ok(false); }
         }
-        if let Some(result) = query.result {
-            if job.result != result { return Ok(false); }
-        }
         Ok(true)
+    }).and_then(|mut jobs| {
+        // a verdict hidden by a freeze must not show through the result filter either
+        if frozen {
+            worker.hide_frozen(&mut jobs)?;
+        }
+        jobs.retain(|job| result.is_none_or(|result| job.result == result));
+        Ok(jobs)
     }) {
         Ok(jobs) => {
             HttpResponse::Ok().json(jobs)
@@ -147,11 +179,24 @@ pub async fn get_jobs(req: HttpRequest, query: web::Query<JobQuery>) -> impl Res
     }
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+struct FreezeQuery {
+    // only administrators may see through a freeze
+    #[serde(default)]
+    pub unfrozen: bool,
+}
+
 #[get("/jobs/{id}")]
-pub async fn get_job_by_id(req: HttpRequest, path: web::Path<i32>) -> impl Responder {
+pub async fn get_job_by_id(req: HttpRequest, query: web::Query<FreezeQuery>, path: web::Path<i32>) -> impl Responder {
     let id = path.into_inner();
     let worker = req.app_data::<Arc<Worker>>().unwrap();
-    match worker.find_job(move |job| Ok(job.id == id)) {
+    let frozen = !(query.unfrozen && require_admin(&req).is_ok());
+    match worker.find_job(move |job| Ok(job.id == id)).and_then(|mut job| {
+        if frozen {
+            worker.hide_frozen(job.as_mut_slice())?;
+        }
+        Ok(job)
+    }) {
         Ok(job) => {
             if let Some(job) = job {
                 HttpResponse::Ok().json(job)
@@ -174,6 +219,61 @@ pub async fn get_job_by_id(req: HttpRequest, path: web::Path<i32>) -> impl Respo
     }
 }
 
+// an admin is whoever logged in as the root user; without authentication everyone is trusted
+fn require_admin(req: &HttpRequest) -> crate::models::Result<()> {
+    let enable_auth = *req.app_data::<bool>().unwrap();
+    if !enable_auth {
+        return Ok(());
+    }
+    let factory = req.app_data::<TokenFactory>().unwrap();
+    let cookie = req.cookie("rustoj-token").ok_or_else(|| {
+        ERR_INVALID_ARGUMENT.clone().with_message("Token required.".into())
+    })?;
+    let payload = factory.parse(&Token::parse(cookie.value())?)?;
+    if payload.address != req.peer_addr().unwrap().ip().to_string() || payload.subject.id != 0 {
+        return Err(ERR_INVALID_ARGUMENT.clone().with_message("Administrator required.".into()));
+    }
+    Ok(())
+}
+
+fn event_stream(events: EventStream) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(events)
+}
+
+#[get("/jobs/{id}/events")]
+pub async fn get_job_events(req: HttpRequest, query: web::Query<FreezeQuery>, path: web::Path<i32>) -> impl Responder {
+    let id = path.into_inner();
+    let worker = req.app_data::<Arc<Worker>>().unwrap();
+    let frozen = !(query.unfrozen && require_admin(&req).is_ok());
+    match worker.subscribe_job(id, frozen) {
+        Ok(events) => event_stream(events),
+        Err(e) => {
+            HttpResponse::build(StatusCode::from_u16(e.http_status).unwrap()).json(json!({
+                "code": e.code,
+                "reason": e.reason,
+                "message": e.message
+            }))
+        }
+    }
+}
+
+// registered ahead of /jobs/{id}, which would otherwise take "events" for an id
+#[get("/jobs/events")]
+pub async fn get_all_job_events(req: HttpRequest) -> impl Responder {
+    if let Err(e) = require_admin(&req) {
+        return HttpResponse::build(StatusCode::from_u16(e.http_status).unwrap()).json(json!({
+            "code": e.code,
+            "reason": e.reason,
+            "message": e.message
+        }));
+    }
+    let worker = req.app_data::<Arc<Worker>>().unwrap();
+    event_stream(worker.subscribe_jobs())
+}
+
 #[put("/jobs/{id}")]
 pub async fn rerun_job(req: HttpRequest, path: web::Path<i32>) -> impl Responder {
     let id = path.into_inner();
@@ -335,15 +435,19 @@ pub async fn login(req: HttpRequest, login: web::Json<UserLogin>) -> impl Respon
 struct RanklistQuery {
     #[serde(default)]
     pub scoring_rule: ScoringRule,
-    pub tie_breaker: Option<TieBreaker>
+    pub tie_breaker: Option<TieBreaker>,
+    // only administrators may see through a freeze
+    #[serde(default)]
+    pub unfrozen: bool,
 }
 
 #[get("/contests/{id}/ranklist")]
 pub async fn get_contest_ranklist(req: HttpRequest, query: web::Query<RanklistQuery>, path: web::Path<i32>) -> impl Responder {
     let worker = req.app_data::<Arc<Worker>>().unwrap();
+    let frozen = !(query.unfrozen && require_admin(&req).is_ok());
     match match path.into_inner() {
-        0 => worker.global_ranklist(query.scoring_rule, query.tie_breaker),
-        id => worker.contest_ranklist(id, query.scoring_rule, query.tie_breaker)
+        0 => worker.global_ranklist(query.scoring_rule, query.tie_breaker, frozen),
+        id => worker.contest_ranklist(id, query.scoring_rule, query.tie_breaker, frozen)
     } {
         Ok(ranklist) => {
             HttpResponse::Ok().json(ranklist)
@@ -358,6 +462,39 @@ pub async fn get_contest_ranklist(req: HttpRequest, query: web::Query<RanklistQu
     }
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+struct ResolveQuery {
+    #[serde(default)]
+    pub scoring_rule: ScoringRule,
+    pub tie_breaker: Option<TieBreaker>,
+    #[serde(default)]
+    pub all: bool,
+}
+
+#[post("/contests/{id}/resolve")]
+pub async fn resolve_contest(req: HttpRequest, query: web::Query<ResolveQuery>, path: web::Path<i32>) -> impl Responder {
+    if let Err(e) = require_admin(&req) {
+        return HttpResponse::build(StatusCode::from_u16(e.http_status).unwrap()).json(json!({
+            "code": e.code,
+            "reason": e.reason,
+            "message": e.message
+        }));
+    }
+    let worker = req.app_data::<Arc<Worker>>().unwrap();
+    match worker.resolve_contest(path.into_inner(), query.scoring_rule, query.tie_breaker, query.all) {
+        Ok(ranklist) => {
+            HttpResponse::Ok().json(ranklist)
+        }
+        Err(e) => {
+            HttpResponse::build(StatusCode::from_u16(e.http_status).unwrap()).json(json!({
+                "code": e.code,
+                "reason": e.reason,
+                "message": e.message
+            }))
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct ContestUpdate {
     pub id: Option<i32>,
@@ -366,7 +503,11 @@ struct ContestUpdate {
     pub to: DateTime<Utc>,
     pub problem_ids: Vec<i32>,
     pub user_ids: Vec<i32>,
-    pub submission_limit: i32
+    pub submission_limit: i32,
+    #[serde(default)]
+    pub practice_after_end: bool,
+    #[serde(default)]
+    pub freeze_at: Option<DateTime<Utc>>
 }
 
 #[post("/contests")]
@@ -390,6 +531,13 @@ pub async fn post_contests(req: HttpRequest, update: web::Json<ContestUpdate>) -
                 problem_ids: update.problem_ids.clone(),
                 user_ids: update.user_ids.clone(),
                 submission_limit: update.submission_limit,
+                practice_after_end: update.practice_after_end,
+                freeze_at: update.freeze_at,
+                // what has been resolved stays resolved
+                resolved: match worker.database().find_contest_by_id(id) {
+                    Ok(Some(contest)) => contest.resolved,
+                    _ => vec![]
+                },
             };
             worker.database().put_contest(&contest)
         },
@@ -402,6 +550,9 @@ pub async fn post_contests(req: HttpRequest, update: web::Json<ContestUpdate>) -
                 problem_ids: update.problem_ids.clone(),
                 user_ids: update.user_ids.clone(),
                 submission_limit: update.submission_limit,
+                practice_after_end: update.practice_after_end,
+                freeze_at: update.freeze_at,
+                resolved: vec![],
             };
             worker.database().put_contest(&contest)
         }
@@ -532,4 +683,40 @@ pub async fn get_problem_by_id(req: HttpRequest, path: web::Path<i32>) -> impl R
             }))
         }
     }
-}
\ No newline at end of file
+}
+
+#[get("/problems/{id}/stats")]
+pub async fn get_problem_stats(req: HttpRequest, query: web::Query<FreezeQuery>, path: web::Path<i32>) -> impl Responder {
+    let worker = req.app_data::<Arc<Worker>>().unwrap();
+    let frozen = !(query.unfrozen && require_admin(&req).is_ok());
+    match worker.problem_stats(path.into_inner(), frozen) {
+        Ok(stats) => {
+            HttpResponse::Ok().json(stats)
+        }
+        Err(e) => {
+            HttpResponse::build(StatusCode::from_u16(e.http_status).unwrap()).json(json!({
+                "code": e.code,
+                "reason": e.reason,
+                "message": e.message
+            }))
+        }
+    }
+}
+
+#[get("/contests/{id}/stats")]
+pub async fn get_contest_stats(req: HttpRequest, query: web::Query<FreezeQuery>, path: web::Path<i32>) -> impl Responder {
+    let worker = req.app_data::<Arc<Worker>>().unwrap();
+    let frozen = !(query.unfrozen && require_admin(&req).is_ok());
+    match worker.contest_stats(path.into_inner(), frozen) {
+        Ok(stats) => {
+            HttpResponse::Ok().json(stats)
+        }
+        Err(e) => {
+            HttpResponse::build(StatusCode::from_u16(e.http_status).unwrap()).json(json!({
+                "code": e.code,
+                "reason": e.reason,
+                "message": e.message
+            }))
+        }
+    }
+}
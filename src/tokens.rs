@@ -9,11 +9,11 @@ pub struct Token {
     pub(crate) payload: Vec<u8>
 }
 
-impl ToString for Token {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let signature_string = URL_SAFE_NO_PAD.encode(&self.signature);
         let payload_string = URL_SAFE_NO_PAD.encode(&self.payload);
-        format!("{}.{}", payload_string, signature_string)
+        write!(f, "{}.{}", payload_string, signature_string)
     }
 }
 
@@ -77,6 +77,6 @@ impl TokenFactory {
         if payload.expires < Local::now() {
             return Err(ERR_INVALID_ARGUMENT.clone().with_message("Token has expired.".into()));
         }
-        return Ok(payload)
+        Ok(payload)
     }
 }
\ No newline at end of file
@@ -1,17 +1,24 @@
 use std::cmp::Ordering;
 use std::collections::VecDeque;
 use std::env::temp_dir;
-use std::fs::{copy, create_dir_all, read_to_string, remove_dir_all, write};
-use std::path::PathBuf;
-use std::process::Command;
-use std::sync::{Arc, Mutex};
+use std::fs::{canonicalize, copy, create_dir, read_to_string, remove_dir_all, write};
+use std::io::ErrorKind;
+use std::os::unix::fs::chown;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread::{spawn, JoinHandle};
 
 use chrono::Utc;
 use rand::Rng;
 
-use crate::database::Database;
-use crate::judge::{standard_judge, strict_judge};
+use crate::database::{Database, JobIndex};
+use crate::events::{EventStream, Events};
+use crate::isolation::Isolation;
+use crate::cgroup::Cgroup;
+use crate::judge::{
+    case_insensitive_judge, checker_failure, float_judge, standard_judge, strict_judge, token_judge, unordered_lines_judge,
+    Resources, Sandbox, Verdict
+};
 use crate::models::*;
 
 struct Tempdir {
@@ -29,11 +36,15 @@ impl Tempdir {
         file_name
     }
     pub fn new() -> Result<Self> {
-        let path = temp_dir().join(Self::random_name());
-        create_dir_all(&path)?;
-        Ok(Self {
-            path
-        })
+        // jobs judged in parallel must never share a directory
+        loop {
+            let path = temp_dir().join(Self::random_name());
+            match create_dir(&path) {
+                Ok(_) => return Ok(Self { path }),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e.into())
+            }
+        }
     }
     pub fn wrap(&self, name: &str) -> String {
         self.path.join(name).to_str().unwrap().into()
@@ -55,13 +66,14 @@ impl Drop for Tempdir {
 }
 
 impl Job {
-    pub fn new(id: i32, submission: &JobRequest, case_len: usize) -> Self {
+    pub fn new(id: i32, submission: &JobRequest, problem: &Problem) -> Self {
         let mut cases = vec![];
-        for i in 0..=case_len {
+        for i in 0..=problem.cases.len() {
             cases.push(JobCase {
                 id: i as _,
                 result: Status::Waiting,
                 time: 0,
+                wall_time: 0,
                 memory: 0,
                 info: "".into()
             })
@@ -74,27 +86,200 @@ impl Job {
             state: JobStatus::Queueing,
             result: Status::Waiting,
             score: 0.0,
-            cases
+            cases,
+            subtasks: Self::subtask_entries(problem)
+        }
+    }
+    fn subtask_entries(problem: &Problem) -> Vec<JobSubtask> {
+        (1..=problem.subtasks().map_or(0, |subtasks| subtasks.len())).map(|id| JobSubtask {
+            id: id as i32,
+            result: Status::Waiting,
+            score: 0.0
+        }).collect()
+    }
+    pub fn reset(&mut self) {
+        self.state = JobStatus::Queueing;
+        self.updated_time = Utc::now();
+        self.hide_verdict();
+    }
+    // all that is left of a submission a freeze hides, for anyone but an admin
+    pub fn hide_verdict(&mut self) {
+        self.result = Status::Waiting;
+        self.score = 0.0;
+        for case in &mut self.cases {
+            case.info = "".into();
+            case.memory = 0;
+            case.time = 0;
+            case.wall_time = 0;
+            case.result = Status::Waiting;
+        }
+        for subtask in &mut self.subtasks {
+            subtask.result = Status::Waiting;
+            subtask.score = 0.0;
+        }
+    }
+    // adds the runtime part of a dynamic ranking score, relative to the fastest accepted times
+    pub fn with_dynamic_score(mut self, problem: &Problem, fastest: Option<&Vec<u64>>) -> Self {
+        if let (Some(ratio), Some(fastest)) = (problem.dynamic_ranking_ratio(), fastest) {
+            if self.result == Status::Accepted {
+                for (i, case) in problem.cases.iter().enumerate() {
+                    if let Some(job_case) = self.cases.get(i+1) {
+                        let time = job_case.time.max(1) as f32;
+                        self.score += case.score * ratio as f32 * fastest[i].max(1) as f32 / time;
+                    }
+                }
+            }
+        }
+        self
+    }
+}
+
+// helpers sorted best first; equal ones share a rank
+fn rankings(helpers: &[RankHelper], tie_breaker: Option<TieBreaker>) -> Vec<Ranking> {
+    let mut result: Vec<Ranking> = vec![];
+    for (i, helper) in helpers.iter().enumerate() {
+        let tied = i != 0 && helper.compare(&helpers[i-1]).is_eq() && match tie_breaker {
+            Some(ref tie_breaker) => tie_breaker.compare(helper, &helpers[i-1]).is_eq(),
+            None => true
+        };
+        result.push(Ranking {
+            user: helper.user.clone(),
+            rank: if tied { result.last().unwrap().rank } else { (i+1) as i32 },
+            scores: helper.jobs.iter().map(|job| match job {
+                Some(job) => job.score,
+                None => 0f32
+            }).collect(),
+            results: helper.jobs.iter().map(|job| job.as_ref().map(|job| job.result)).collect(),
+            icpc: helper.icpc.clone(),
+            pending: helper.pending.clone()
+        });
+    }
+    result
+}
+
+fn hidden_by_freeze(contests: &[Contest], job: &Job) -> bool {
+    contests.iter().any(|contest| contest.id == job.submission.contest_id && contest.hides(job))
+}
+
+// cases weigh by their scores, or equally when none has a score
+fn weighted_ratio(ratios: &[(f32, f32)]) -> f32 {
+    let total = ratios.iter().map(|(weight, _)| weight).sum::<f32>();
+    if ratios.is_empty() {
+        1.0
+    }
+    else if total > 0.0 {
+        ratios.iter().map(|(weight, ratio)| weight * ratio).sum::<f32>() / total
+    }
+    else {
+        ratios.iter().map(|(_, ratio)| ratio).sum::<f32>() / ratios.len() as f32
+    }
+}
+
+// the share of its score a case earned
+fn earned(status: Status, fraction: f32) -> f32 {
+    match status {
+        Status::Accepted | Status::PartiallyCorrect => fraction,
+        _ => 0.0
+    }
+}
+
+// a failed case stops the subtask, unless the policy sums every case
+fn fails_subtask(policy: SubtaskPolicy, status: Status, fraction: f32) -> bool {
+    match policy {
+        SubtaskPolicy::Sum => status != Status::Accepted && status != Status::PartiallyCorrect,
+        SubtaskPolicy::MinRatio => earned(status, fraction) == 0.0,
+        SubtaskPolicy::AllOrNothing => status != Status::Accepted
+    }
+}
+
+// scores a subtask from the weight, status and checker fraction of each case it ran, giving the share of its score,
+// its status, and whether it passed in full, as the subtasks depending on it require
+fn score_subtask(policy: SubtaskPolicy, ready: bool, cases: &[(f32, Status, f32)]) -> (f32, Status, bool) {
+    if !ready {
+        return (0.0, Status::Skipped, false);
+    }
+    let ratios = cases.iter().map(|(weight, status, fraction)| (*weight, earned(*status, *fraction))).collect::<Vec<_>>();
+    let failure = cases.iter()
+        .find(|(_, status, fraction)| fails_subtask(policy, *status, *fraction))
+        .map(|(_, status, _)| *status);
+    let ratio = match (policy, failure) {
+        (SubtaskPolicy::Sum, _) => weighted_ratio(&ratios),
+        (_, Some(_)) => 0.0,
+        (SubtaskPolicy::MinRatio, None) => ratios.iter().map(|(_, ratio)| *ratio).fold(1.0, f32::min),
+        (SubtaskPolicy::AllOrNothing, None) => weighted_ratio(&ratios)
+    };
+    let passed = failure.is_none() && ratios.iter().all(|(_, ratio)| *ratio >= 1.0);
+    let status = match failure {
+        Some(status) => status,
+        None if passed => Status::Accepted,
+        None => Status::PartiallyCorrect
+    };
+    (ratio, status, passed)
+}
+
+fn allowed_cpus() -> Vec<usize> {
+    unsafe {
+        let mut cpu_set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut cpu_set) != 0 {
+            return vec![];
         }
+        (0..libc::CPU_SETSIZE as usize).filter(|cpu| libc::CPU_ISSET(*cpu, &cpu_set)).collect()
     }
 }
 
+// paths relative to the server stop resolving once a sandbox enters its own root, so every argument
+// naming a file is made absolute and mounted where it is, and the working directory becomes /sandbox;
+// configured mounts given by relative paths land where the absolute command expects them as well
+fn checker_isolation(command: &[String], bind_mounts: &[BindMount], workdir: &Path) -> Result<(Isolation, Vec<String>)> {
+    let command = command.iter().map(|arg| match canonicalize(arg) {
+        Ok(path) if Path::new(arg).is_relative() && path.is_file() => path.to_str().map_or(arg.clone(), Into::into),
+        _ => arg.clone()
+    }).collect::<Vec<_>>();
+    let bind_mounts = bind_mounts.iter().map(|bind| match canonicalize(&bind.source) {
+        Ok(path) if Path::new(&bind.source).is_relative() => BindMount {
+            source: path.to_str().map_or(bind.source.clone(), Into::into),
+            ..bind.clone()
+        },
+        _ => bind.clone()
+    }).chain(command.iter()
+        .filter(|arg| Path::new(arg).is_absolute() && Path::new(arg).is_file() && !Path::new(arg).starts_with(workdir))
+        .map(|arg| BindMount { source: arg.clone(), target: None, writable: false })
+    ).collect::<Vec<_>>();
+    let isolation = Isolation::new(None, &bind_mounts, workdir, false)?;
+    let command = command.iter().map(|arg| isolation.inner_path(arg)).collect();
+    Ok((isolation, command))
+}
+
+// unprivileged user namespaces can be turned off, as by Docker's default seccomp profile or AppArmor,
+// and then every isolated case would end in a system error
+fn isolation_works() -> Result<bool> {
+    let tempdir = Tempdir::new()?;
+    let sandbox = Sandbox {
+        filter: SyscallPolicy::default().compile()?,
+        cgroup_root: None,
+        isolation: Some(Isolation::new(None, &[], &tempdir.path, false)?),
+        cpu: None
+    };
+    let command = ["sh".into(), "-c".into(), "exit".into()];
+    let (result, _) = CheckerLimits::default().as_case().execute(&sandbox, &command, "/dev/null", &tempdir.random(), None);
+    tempdir.clean()?;
+    Ok(result == Ok(0))
+}
+
 // ephemeral lock stage
 fn lock<'a, T, F, R>(value: &'a Mutex<T>, op: F) -> R where F: FnOnce(&mut T) -> R + 'a {
     let mut locked = value.lock().unwrap();
     op(&mut *locked)
 }
 
-fn update_job<'a, F>(value: &'a Mutex<Job>, op: F) where F: FnOnce(&mut Job) + 'a {
-    lock(value, op);
-    lock(value, |job| job.updated_time = Utc::now());
-}
-
 pub struct Worker {
     pub config: Config,
     queue: Mutex<VecDeque<Arc<Mutex<Job>>>>,
+    available: Condvar,
     running: Mutex<bool>,
     db: Database,
+    events: Events,
+    cgroup_root: Option<PathBuf>,
     job_id: Mutex<i32>,
     user_id: Mutex<i32>,
     contest_id: Mutex<i32>,
@@ -102,17 +287,78 @@ pub struct Worker {
 
 impl Worker {
     pub fn new(config: Config, flush_data: bool) -> Result<Self> {
+        for problem in &config.problems {
+            problem.validate(&config.languages)?;
+        }
+        if config.server.isolation && !isolation_works()? {
+            return Err(ERR_INTERNAL.clone().with_message(
+                "Sandboxes cannot enter their own namespaces here, set isolation to false in the server config to judge without them.".into()
+            ));
+        }
         let db = Database::new(flush_data)?;
+        let cgroup_root = config.server.cgroup_root.as_ref().and_then(|root| {
+            match Cgroup::setup_root(root) {
+                Ok(root) => Some(root),
+                Err(e) => {
+                    eprintln!("cgroup v2 unavailable at {} ({}), falling back to rlimits.", root, e);
+                    None
+                }
+            }
+        });
+        // jobs cut off by a crash or an exit are judged again from scratch
+        let mut pending = db.find_jobs(|job| Ok(job.state == JobStatus::Queueing || job.state == JobStatus::Running))?;
+        pending.sort_by_key(|job| job.id);
+        let queue = pending.into_iter().map(|mut job| {
+            job.reset();
+            Arc::new(Mutex::new(job))
+        }).collect();
         Ok(Self {
             config,
-            queue: Mutex::new(VecDeque::new()),
+            queue: Mutex::new(queue),
+            available: Condvar::new(),
             running: Mutex::new(false),
+            events: Events::new(),
+            cgroup_root,
             job_id: Mutex::new(db.largest_job_id()?),
             user_id: Mutex::new(db.largest_user_id()?),
             contest_id: Mutex::new(db.largest_contest_id()?),
             db,
         })
     }
+    // every change goes out to the subscribers while the job is still locked, so they see them in order
+    fn update_job<F>(&self, value: &Mutex<Job>, op: F) where F: FnOnce(&mut Job) {
+        lock(value, |job| {
+            op(job);
+            job.updated_time = Utc::now();
+            self.events.publish(job);
+        });
+    }
+    pub fn subscribe_job(&self, id: i32, frozen: bool) -> Result<EventStream> {
+        let contests = self.db.list_contests()?;
+        let hidden = |job: &Job| frozen && hidden_by_freeze(&contests, job);
+        let events = lock(&self.queue, |queue| {
+            queue.iter()
+                .find(|job| lock(job, |job| job.id == id))
+                .map(|job| lock(job, |job| self.events.subscribe_job(job, hidden(job))))
+        });
+        match events {
+            Some(events) => Ok(events),
+            None => match self.db.get_job(id)? {
+                Some(job) => Ok(self.events.subscribe_job(&job, hidden(&job))),
+                None => Err(ERR_NOT_FOUND.clone().with_message(format!("Job {} not found.", id)))
+            }
+        }
+    }
+    pub fn hide_frozen(&self, jobs: &mut [Job]) -> Result<()> {
+        let contests = self.db.list_contests()?;
+        for job in jobs.iter_mut().filter(|job| hidden_by_freeze(&contests, job)) {
+            job.hide_verdict();
+        }
+        Ok(())
+    }
+    pub fn subscribe_jobs(&self) -> EventStream {
+        self.events.subscribe_all()
+    }
     pub fn create_job(&self, request: &JobRequest) -> Result<Job> {
         if !self.db.user_exists(request.user_id)? {
             return Err(ERR_NOT_FOUND.clone().with_message(format!("User {} not found.", request.user_id))); 
@@ -132,50 +378,72 @@ impl Worker {
             if !contest.problem_ids.contains(&request.problem_id) || !contest.user_ids.contains(&request.user_id) {
                 return Err(ERR_INVALID_ARGUMENT.clone());
             }
-            let job_count = self.find_jobs(|job| {
-                Ok(
-                    job.submission.contest_id == request.contest_id &&
-                    job.submission.problem_id == request.problem_id &&
-                    job.submission.user_id == request.user_id
-                )
-            })?.len() as i32;
-            if job_count >= contest.submission_limit {
-                return Err(ERR_RATE_LIMIT.clone());
+            let now = Utc::now();
+            if now < contest.from {
+                return Err(ERR_CONTEST_CLOSED.clone().with_message(format!("Contest {} has not started.", contest.id)));
+            }
+            if now > contest.to && !contest.practice_after_end {
+                return Err(ERR_CONTEST_CLOSED.clone().with_message(format!("Contest {} has ended.", contest.id)));
+            }
+            // practice submissions are not limited, and do not use up the limit
+            if now <= contest.to {
+                let index = JobIndex::UserProblem(request.user_id, request.problem_id);
+                let job_count = self.find_jobs_in(index, |job| {
+                    Ok(
+                        job.submission.contest_id == request.contest_id &&
+                        job.submission.problem_id == request.problem_id &&
+                        job.submission.user_id == request.user_id &&
+                        job.created_time <= contest.to
+                    )
+                })?.len() as i32;
+                if job_count >= contest.submission_limit {
+                    return Err(ERR_RATE_LIMIT.clone());
+                }
             }
         }
-        let language = self.config.languages.iter().find(
-            |lang| lang.name == request.language
-        );
-        if language.is_none() {
+        let problem = problem.unwrap();
+        if let ProblemType::OutputOnly = problem.problem_type {
+            if request.outputs.len() != problem.cases.len() {
+                return Err(ERR_INVALID_ARGUMENT.clone().with_message(
+                    format!("Problem {} expects {} outputs.", problem.id, problem.cases.len())
+                ));
+            }
+        }
+        else if !self.config.languages.iter().any(|lang| lang.name == request.language) {
             return Err(ERR_NOT_FOUND.clone().with_message(format!("Language {} not found.", request.language)));
         }
         Ok(Job::new({ 
             let mut v = self.job_id.lock().unwrap();
             *v += 1;
             *v
-        }, request, problem.unwrap().cases.len()))
+        }, request, problem))
     }
-    pub fn run(&self, job: &Mutex<Job>) {
-        let result = self.run_unsafe(job);
+    pub fn run(&self, job: &Mutex<Job>, cpu: Option<usize>) {
+        let result = self.run_unsafe(job, cpu);
         if result.is_err() {
-            update_job(job, |job| {
+            self.update_job(job, |job| {
                 job.result = Status::SystemError;
+                job.state = JobStatus::Finished;
             });
         }
     }
-    fn run_unsafe(&self, job: &Mutex<Job>) -> Result<()> {
-        update_job(job, |job| { 
+    fn run_unsafe(&self, job: &Mutex<Job>, cpu: Option<usize>) -> Result<()> {
+        self.update_job(job, |job| { 
             job.result = Status::Running;
             job.state = JobStatus::Running;
         });
-        let language = self.config.languages.iter().find(
-            |lang| lock(job, |job| lang.name == job.submission.language)
-        );
-        let language = language.unwrap();
-        let problem = self.config.problems.iter().find(
-            |problem| problem.id == lock(job, |job| job.submission.problem_id)
-        );
-        let problem = problem.unwrap();
+        // a job resumed after a restart may name a problem or language the config no longer has
+        let problem_id = lock(job, |job| job.submission.problem_id);
+        let problem = self.config.problems.iter().find(|problem| problem.id == problem_id).ok_or_else(|| {
+            ERR_NOT_FOUND.clone().with_message(format!("Problem {} not found.", problem_id))
+        })?;
+        if let ProblemType::OutputOnly = problem.problem_type {
+            return self.run_output_only(job, problem);
+        }
+        let language_name = lock(job, |job| job.submission.language.clone());
+        let language = self.config.languages.iter().find(|lang| lang.name == language_name).ok_or_else(|| {
+            ERR_NOT_FOUND.clone().with_message(format!("Language {} not found.", language_name))
+        })?;
 
         let tempdir = Tempdir::new()?;
         let source_file_name = tempdir.wrap(&language.file_name);
@@ -183,199 +451,194 @@ impl Worker {
         let input_file_name = tempdir.random();
         let output_file_name = tempdir.random();
         write(&source_file_name, lock(job, |job| job.submission.source_code.clone()))?;
+        // a grader's headers sit next to the submission, where an #include "..." finds them
+        let mut extra_sources = vec![];
+        if let Some(extra_files) = problem.extra_files.get(&language.name) {
+            for (i, file) in extra_files.sources.iter().chain(&extra_files.headers).enumerate() {
+                let name = Path::new(file).file_name().and_then(|name| name.to_str()).ok_or_else(|| {
+                    ERR_INTERNAL.clone().with_message(format!("Invalid extra file {}.", file))
+                })?;
+                let target = tempdir.wrap(name);
+                copy(file, &target)?;
+                if i < extra_files.sources.len() {
+                    extra_sources.push(target);
+                }
+            }
+        }
 
-        if !language.compile(&source_file_name, &exe_file_name) {
-            update_job(job, |job| {
-                job.cases[0].result = Status::CompilationError;
-                job.result = Status::CompilationError;
-                job.state = JobStatus::Finished;
+        if language.command.is_empty() {
+            self.update_job(job, |job| {
+                job.cases[0].result = Status::Skipped;
+            });
+        }
+        else {
+            // the compiler sees no more than the solution does, and writes next to the source, with a /tmp of its own
+            let scratch = Tempdir::new()?;
+            let mut bind_mounts = language.bind_mounts.clone();
+            bind_mounts.push(BindMount { source: scratch.path.to_str().unwrap().into(), target: Some("/tmp".into()), writable: true });
+            let sandbox = Sandbox {
+                filter: language.compile_limits.syscalls.compile()?,
+                cgroup_root: self.cgroup_root.as_deref(),
+                isolation: if self.config.server.isolation {
+                    let (uid, gid) = Isolation::host_ids();
+                    chown(&tempdir.path, Some(uid), Some(gid))?;
+                    chown(&scratch.path, Some(uid), Some(gid))?;
+                    Some(Isolation::new(language.rootfs.as_deref(), &bind_mounts, &tempdir.path, true)?)
+                } else {
+                    None
+                },
+                cpu
+            };
+            let (success, message) = language.compile(
+                &sandbox, &source_file_name, &extra_sources, &exe_file_name, &tempdir.random(), &tempdir.random()
+            );
+            scratch.clean()?;
+            if !success {
+                self.update_job(job, |job| {
+                    job.cases[0].result = Status::CompilationError;
+                    job.cases[0].info = message;
+                    job.result = Status::CompilationError;
+                    job.state = JobStatus::Finished;
+                });
+                tempdir.clean()?;
+                return Ok(())
+            }
+            self.update_job(job, |job| {
+                job.cases[0].result = Status::CompilationSuccess;
+                job.cases[0].info = message;
             });
-            tempdir.clean()?;
-            return Ok(())
         }
 
-        update_job(job, |job| {
-            job.cases[0].result = Status::CompilationSuccess;
-        });
+        let sandbox = Sandbox {
+            filter: language.syscalls.compile()?,
+            cgroup_root: self.cgroup_root.as_deref(),
+            isolation: if self.config.server.isolation {
+                Some(Isolation::new(language.rootfs.as_deref(), &language.bind_mounts, &tempdir.path, false)?)
+            } else {
+                None
+            },
+            cpu
+        };
+        let command = language.expand_run_command(
+            &sandbox.path(&exe_file_name), 
+            &sandbox.path(&source_file_name), 
+            &sandbox.path(tempdir.path.to_str().unwrap())
+        );
 
-        if let MiscType::Packed { packing } = &problem.misc {
-            for group in packing {
-                let mut group_valid = true;
-                let mut group_score = 0f32;
-                for i in group {
+        if let Some(subtasks) = problem.subtasks() {
+            // jobs stored before subtasks were reported have no entries for them
+            self.update_job(job, |job| {
+                if job.subtasks.len() != subtasks.len() {
+                    job.subtasks = Job::subtask_entries(problem);
+                }
+            });
+            let mut passed = vec![];
+            // a case shared by several subtasks is only run once
+            let mut outcomes: Vec<Option<(Status, f32)>> = vec![None; problem.cases.len() + 1];
+            for (k, subtask) in subtasks.iter().enumerate() {
+                // only an earlier subtask can be a prerequisite, as checked when the config was loaded
+                let ready = subtask.dependencies.iter().all(|dependency| passed[dependency - 1]);
+                self.update_job(job, |job| {
+                    job.subtasks[k].result = if ready { Status::Running } else { Status::Skipped };
+                });
+                // each case run, by its weight, status and the fraction the checker gave
+                let mut ran = vec![];
+                let mut failed = false;
+                for i in &subtask.cases {
                     let i = *i as usize;
                     let case = &problem.cases[i-1];
-                    if !group_valid {
-                        update_job(job, |job| {
-                            job.cases[i].result = Status::Skipped;
-                        });
+                    if !ready || (failed && subtask.policy != SubtaskPolicy::Sum) {
+                        if outcomes[i].is_none() {
+                            self.update_job(job, |job| {
+                                job.cases[i].result = Status::Skipped;
+                            });
+                        }
                         continue;
                     }
-                    update_job(job, |job| {
-                        job.cases[i].result = Status::Running;
-                    });
-                    copy(&case.input_file, &input_file_name)?;
-                    let (result, resources) = 
-                        case.run(&exe_file_name, &input_file_name, &output_file_name);
-                    match result {
-                        Ok(_) => {
-                            match match problem.problem_type {
-                                ProblemType::Standard => {
-                                    let got = read_to_string(&output_file_name)?;
-                                    let expected = read_to_string(&case.answer_file)?;
-                                    standard_judge(&got, &expected)
-                                }
-                                ProblemType::Strict => {
-                                    let got = read_to_string(&output_file_name)?;
-                                    let expected = read_to_string(&case.answer_file)?;
-                                    strict_judge(&got, &expected)
-                                }
-                                ProblemType::SpecialJudge => {
-                                    if let MiscType::SpecialJudge { special_judge } = &problem.misc {
-                                        let special_judge = special_judge.iter().map(|segment| {
-                                            dbg!(segment);
-                                            match segment.as_str() {
-                                                "%OUTPUT%" => &output_file_name,
-                                                "%ANSWER%" => &case.answer_file,
-                                                s => s
-                                            }
-                                        }).collect::<Vec<&str>>();
-                                        let output =
-                                            Command::new(&special_judge[0])
-                                            .args(&special_judge[1..])
-                                            .output()?;
-                                        let stdout = String::from_utf8(output.stdout).unwrap();
-                                        let lines = stdout.split("\n").collect::<Vec<&str>>();
-                                        if lines.len() != 2 {
-                                            Status::SpecialJudgeError
-                                        }
-                                        else {
-                                            let status = serde_json::from_str(&format!("\"{}\"", lines[0]));
-                                            match status {
-                                                Ok(status) => {
-                                                    update_job(job, |job| {
-                                                        job.cases[i].info = lines[1].into();
-                                                    });
-                                                    status
-                                                }
-                                                Err(_) => Status::SpecialJudgeError
-                                            }
-                                        }
-                                    }
-                                    else {
-                                        Status::SystemError
-                                    }
-                                }
-                                _ => Status::Accepted
-                            } {
-                                Status::Accepted => {
-                                    update_job(job, |job| {
-                                        job.cases[i].result = Status::Accepted;
-                                        group_score += case.score;
-                                    });
-                                }
-                                status => {
-                                    group_valid = false;
-                                    group_score = 0f32;
-                                    update_job(job, |job| {
-                                        job.cases[i].result = status;
-                                        if job.result == Status::Running {
-                                            job.result = status;
-                                        }
+                    let (status, fraction) = match outcomes[i] {
+                        Some(outcome) => outcome,
+                        None => {
+                            self.update_job(job, |job| {
+                                job.cases[i].result = Status::Running;
+                            });
+                            copy(&case.input_file, &input_file_name)?;
+                            let (result, resources) = 
+                                self.run_case(problem, case, &sandbox, &command, &tempdir, &input_file_name, &output_file_name)?;
+                            let (status, fraction) = match result {
+                                Ok(verdict) => {
+                                    // a special judge may give only part of the case score
+                                    let (status, fraction, info) = 
+                                        self.judge_output(problem, case, &tempdir, &output_file_name, verdict)?;
+                                    self.update_job(job, |job| {
+                                        job.cases[i].info = info;
                                     });
+                                    (status, fraction)
                                 }
-                            }
-                        }
-                        Err(status) => {
-                            group_valid = false;
-                            group_score = 0f32;
-                            update_job(job, |job| {
+                                Err(status) => (status, 0.0)
+                            };
+                            self.update_job(job, |job| {
                                 job.cases[i].result = status;
-                                if job.result == Status::Running {
+                                if status != Status::Accepted && job.result == Status::Running {
                                     job.result = status;
                                 }
+                                job.cases[i].time = resources.time;
+                                job.cases[i].wall_time = resources.wall_time;
+                                job.cases[i].memory = resources.memory;
                             });
+                            outcomes[i] = Some((status, fraction));
+                            (status, fraction)
                         }
-                    }
-                    update_job(job, |job| {
-                        job.cases[i].time = resources.time;
-                        job.cases[i].memory = resources.memory;
-                    });
+                    };
+                    failed |= fails_subtask(subtask.policy, status, fraction);
+                    ran.push((case.score, status, fraction));
                 }
-                update_job(job, |job| {
-                    job.score += group_score;
+                let (ratio, status, full) = score_subtask(subtask.policy, ready, &ran);
+                let full_score = subtask.score.unwrap_or_else(|| {
+                    subtask.cases.iter().map(|i| problem.cases[*i as usize - 1].score).sum()
+                });
+                passed.push(full);
+                self.update_job(job, |job| {
+                    job.subtasks[k].score = full_score * ratio;
+                    job.subtasks[k].result = status;
+                    job.score += full_score * ratio;
                 });
             }
         }
         else {
+            // under dynamic ranking, the rest of the score is given out by the ranklists
+            let correctness_ratio = 1.0 - problem.dynamic_ranking_ratio().unwrap_or(0.0) as f32;
             for (i, case) in problem.cases.iter().enumerate() {
-                update_job(job, |job| {
+                self.update_job(job, |job| {
                     job.cases[i+1].result = Status::Running;
                 });
                 copy(&case.input_file, &input_file_name)?;
                 let (result, resources) = 
-                    case.run(&exe_file_name, &input_file_name, &output_file_name);
+                    self.run_case(problem, case, &sandbox, &command, &tempdir, &input_file_name, &output_file_name)?;
                 match result {
-                    Ok(_) => {
-                        match match problem.problem_type {
-                            ProblemType::Standard => {
-                                let got = read_to_string(&output_file_name)?;
-                                let expected = read_to_string(&case.answer_file)?;
-                                standard_judge(&got, &expected)
-                            }
-                            ProblemType::Strict => {
-                                let got = read_to_string(&output_file_name)?;
-                                let expected = read_to_string(&case.answer_file)?;
-                                strict_judge(&got, &expected)
-                            }
-                            ProblemType::SpecialJudge => {
-                                if let MiscType::SpecialJudge { special_judge } = &problem.misc {
-                                    let special_judge = special_judge.iter().map(|segment| {
-                                        dbg!(segment);
-                                        match segment.as_str() {
-                                            "%OUTPUT%" => &output_file_name,
-                                            "%ANSWER%" => &case.answer_file,
-                                            s => s
-                                        }
-                                    }).collect::<Vec<&str>>();
-                                    dbg!(&special_judge);
-                                    let output =
-                                        Command::new(&special_judge[0])
-                                        .args(&special_judge[1..])
-                                        .output()?;
-                                    dbg!(&output);
-                                    let stdout = String::from_utf8(output.stdout).unwrap();
-                                    let lines = stdout.split_terminator("\n").collect::<Vec<&str>>();
-                                    if lines.len() != 2 {
-                                        Status::SpecialJudgeError
-                                    }
-                                    else {
-                                        let status = serde_json::from_str(&format!("\"{}\"", lines[0]));
-                                        match status {
-                                            Ok(status) => {
-                                                update_job(job, |job| {
-                                                    job.cases[i+1].info = lines[1].into();
-                                                });
-                                                status
-                                            }
-                                            Err(_) => Status::SpecialJudgeError
-                                        }
-                                    }
-                                }
-                                else {
-                                    Status::SystemError
-                                }
-                            }
-                            _ => Status::Accepted
-                        } {
+                    Ok(verdict) => {
+                        let (status, fraction, info) = 
+                            self.judge_output(problem, case, &tempdir, &output_file_name, verdict)?;
+                        self.update_job(job, |job| {
+                            job.cases[i+1].info = info;
+                        });
+                        match status {
                             Status::Accepted => {
-                                update_job(job, |job| {
+                                self.update_job(job, |job| {
                                     job.cases[i+1].result = Status::Accepted;
-                                    job.score += case.score;
+                                    job.score += case.score * fraction * correctness_ratio;
+                                });
+                            }
+                            Status::PartiallyCorrect => {
+                                self.update_job(job, |job| {
+                                    job.cases[i+1].result = Status::PartiallyCorrect;
+                                    if job.result == Status::Running {
+                                        job.result = Status::PartiallyCorrect;
+                                    }
+                                    job.score += case.score * fraction * correctness_ratio;
                                 });
                             }
                             status => {
-                                update_job(job, |job| {
+                                self.update_job(job, |job| {
                                     job.cases[i+1].result = status;
                                     if job.result == Status::Running {
                                         job.result = status;
@@ -385,7 +648,7 @@ impl Worker {
                         }
                     }
                     Err(status) => {
-                        update_job(job, |job| {
+                        self.update_job(job, |job| {
                             job.cases[i+1].result = status;
                             if job.result == Status::Running {
                                 job.result = status;
@@ -393,13 +656,14 @@ impl Worker {
                         });
                     }
                 }
-                update_job(job, |job| {
+                self.update_job(job, |job| {
                     job.cases[i+1].time = resources.time;
+                    job.cases[i+1].wall_time = resources.wall_time;
                     job.cases[i+1].memory = resources.memory;
                 });
             }
         }
-        update_job(job, |job| {
+        self.update_job(job, |job| {
             if job.result == Status::Running {
                 job.result = Status::Accepted;
             }
@@ -408,23 +672,170 @@ impl Worker {
 
         Ok(())
     }
-    pub fn start(self: &Arc<Self>) -> JoinHandle<()> {
-        let this = Arc::clone(self);
-        lock(&self.running, |v| *v = true);
-        spawn(move || {
-            while lock(&this.running, |v| *v) {
-                if lock(&this.queue, |queue| queue.is_empty()) {
-                    continue;
-                }
-                let job = lock(&this.queue, |queue| Arc::clone(&queue.front().unwrap()));
-                this.run(&job);
-                let job = lock(&this.queue, |queue| queue.pop_front()).unwrap();
-                lock(&job, |job| this.db.put_job(job)).ok(); // discard this error, as there is no way to handle it.
+    // checks a case's output with the problem's checker, giving an empty info when there is nothing to tell
+    fn judge_output(
+        &self, problem: &Problem, case: &Case, tempdir: &Tempdir, output_file: &str, verdict: Option<Verdict>
+    ) -> Result<Verdict> {
+        let got = || read_to_string(output_file);
+        let expected = || read_to_string(&case.answer_file);
+        let diff = |(status, info): (Status, String)| (status, 1.0, if problem.hide_diff { "".into() } else { info });
+        Ok(match problem.checker() {
+            ProblemType::Standard | ProblemType::DynamicRanking | ProblemType::OutputOnly => {
+                diff(standard_judge(&got()?, &expected()?))
+            }
+            ProblemType::Strict => diff(strict_judge(&got()?, &expected()?)),
+            ProblemType::Token => (token_judge(&got()?, &expected()?), 1.0, "".into()),
+            ProblemType::CaseInsensitive => (case_insensitive_judge(&got()?, &expected()?), 1.0, "".into()),
+            ProblemType::UnorderedLines => (unordered_lines_judge(&got()?, &expected()?), 1.0, "".into()),
+            ProblemType::Float => {
+                let (absolute, relative) = problem.float_epsilon();
+                diff(float_judge(&got()?, &expected()?, absolute, relative))
             }
+            ProblemType::SpecialJudge => self.special_judge(problem, case, tempdir, output_file)?,
+            ProblemType::Interactive => verdict.unwrap_or((Status::SystemError, 0.0, "".into()))
         })
     }
-    pub fn push_job(&self, job: Arc<Mutex<Job>>) {
+    // uploaded outputs skip compiling and running, and every case is scored on its own
+    fn run_output_only(&self, job: &Mutex<Job>, problem: &Problem) -> Result<()> {
+        let tempdir = Tempdir::new()?;
+        let output_file_name = tempdir.random();
+        let outputs = lock(job, |job| job.submission.outputs.clone());
+        self.update_job(job, |job| {
+            job.cases[0].result = Status::Skipped;
+        });
+        for (i, (case, output)) in problem.cases.iter().zip(outputs).enumerate() {
+            self.update_job(job, |job| {
+                job.cases[i+1].result = Status::Running;
+            });
+            write(&output_file_name, output)?;
+            let (status, fraction, info) = self.judge_output(problem, case, &tempdir, &output_file_name, None)?;
+            self.update_job(job, |job| {
+                job.cases[i+1].result = status;
+                job.cases[i+1].info = info;
+                if status == Status::Accepted || status == Status::PartiallyCorrect {
+                    job.score += case.score * fraction;
+                }
+                if status != Status::Accepted && job.result == Status::Running {
+                    job.result = status;
+                }
+            });
+        }
+        self.update_job(job, |job| {
+            if job.result == Status::Running {
+                job.result = Status::Accepted;
+            }
+            job.state = JobStatus::Finished;
+        });
+        Ok(())
+    }
+    // blocks until a queueing job is claimed, or returns None once the worker stops
+    fn next_job(&self) -> Option<Arc<Mutex<Job>>> {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            if !lock(&self.running, |v| *v) {
+                return None;
+            }
+            if let Some(job) = queue.iter().find(|job| lock(job, |job| job.state == JobStatus::Queueing)) {
+                let job = Arc::clone(job);
+                self.update_job(&job, |job| job.state = JobStatus::Running);
+                return Some(job);
+            }
+            queue = self.available.wait(queue).unwrap();
+        }
+    }
+    // the checker sees only the files its command names and those configured for it,
+    // and gets the command as it reads inside the sandbox
+    fn checker_sandbox(&self, command: Vec<String>, limits: &CheckerLimits, tempdir: &Tempdir) -> Result<(Sandbox<'_>, Vec<String>)> {
+        let (isolation, command) = if self.config.server.isolation {
+            let (isolation, command) = checker_isolation(&command, &limits.bind_mounts, &tempdir.path)?;
+            (Some(isolation), command)
+        } else {
+            (None, command)
+        };
+        let sandbox = Sandbox {
+            filter: limits.syscalls.compile()?,
+            cgroup_root: self.cgroup_root.as_deref(),
+            isolation,
+            cpu: None
+        };
+        Ok((sandbox, command))
+    }
+    fn special_judge(&self, problem: &Problem, case: &Case, tempdir: &Tempdir, output_file: &str) -> Result<Verdict> {
+        let MiscType::SpecialJudge { special_judge, checker_protocol, checker_limits } = &problem.misc else {
+            return Ok((Status::SystemError, 0.0, "".into()));
+        };
+        let mut command = special_judge.iter().map(|segment| {
+            match segment.as_str() {
+                "%INPUT%" => case.input_file.clone(),
+                "%OUTPUT%" => output_file.into(),
+                "%ANSWER%" => case.answer_file.clone(),
+                s => s.into()
+            }
+        }).collect::<Vec<String>>();
+        if *checker_protocol == CheckerProtocol::Testlib {
+            command.extend([case.input_file.clone(), output_file.into(), case.answer_file.clone()]);
+        }
+        let (sandbox, command) = self.checker_sandbox(command, checker_limits, tempdir)?;
+        let stdout_file = tempdir.random();
+        let stderr_file = tempdir.random();
+        let (result, _) = checker_limits.as_case().execute(&sandbox, &command, "/dev/null", &stdout_file, Some(&stderr_file));
+        let exit_code = match result {
+            Ok(exit_code) => exit_code,
+            Err(status) => return Ok((Status::SpecialJudgeError, 0.0, checker_failure(status)))
+        };
+        let stdout = read_to_string(&stdout_file)?;
+        let stderr = read_to_string(&stderr_file)?;
+        Ok(checker_protocol.parse(exit_code, &stdout, &stderr))
+    }
+    // runs the solution on a case, and for an interactive problem gives back the interactor's verdict as well
+    #[allow(clippy::too_many_arguments)]
+    fn run_case(
+        &self, problem: &Problem, case: &Case, sandbox: &Sandbox, command: &[String], 
+        tempdir: &Tempdir, input_file: &str, output_file: &str
+    ) -> Result<(std::result::Result<Option<Verdict>, Status>, Resources)> {
+        let MiscType::Interactive { interactor, interactor_limits } = &problem.misc else {
+            let (result, resources) = case.run(sandbox, command, input_file, output_file);
+            return Ok((result.map(|_| None), resources));
+        };
+        let interactor_command = interactor.iter().map(|segment| {
+            match segment.as_str() {
+                "%INPUT%" => case.input_file.clone(),
+                "%ANSWER%" => case.answer_file.clone(),
+                s => s.into()
+            }
+        }).collect::<Vec<String>>();
+        let (interactor_sandbox, interactor_command) = self.checker_sandbox(interactor_command, interactor_limits, tempdir)?;
+        // the interactor waits on the solution, so it shares the wall clock limit of the case
+        let mut interactor_case = interactor_limits.as_case();
+        interactor_case.wall_time_limit = case.effective_wall_time_limit().max(interactor_case.effective_wall_time_limit());
+        let (result, resources) = case.interact(
+            sandbox, command, &interactor_case, &interactor_sandbox, &interactor_command, &tempdir.random()
+        );
+        Ok((result.map(Some), resources))
+    }
+    pub fn start(self: &Arc<Self>) -> Vec<JoinHandle<()>> {
+        lock(&self.running, |v| *v = true);
+        let cpus = if self.config.server.pin_cpus { allowed_cpus() } else { vec![] };
+        (0..self.config.server.judge_threads.max(1)).map(|slot| {
+            let this = Arc::clone(self);
+            let cpu = if cpus.is_empty() { None } else { Some(cpus[slot % cpus.len()]) };
+            spawn(move || {
+                while let Some(job) = this.next_job() {
+                    lock(&job, |job| this.db.put_job(job)).ok();
+                    this.run(&job, cpu);
+                    // stored before leaving the queue, so lookups never miss it in between
+                    lock(&job, |job| this.db.put_job(job)).ok(); // discard this error, as there is no way to handle it.
+                    lock(&this.queue, |queue| queue.retain(|queued| !Arc::ptr_eq(queued, &job)));
+                }
+            })
+        }).collect()
+    }
+    // stored right away, so an accepted job survives a restart
+    pub fn push_job(&self, job: Arc<Mutex<Job>>) -> Result<()> {
+        lock(&job, |job| self.db.put_job(job))?;
         lock(&self.queue, |queue| queue.push_back(job));
+        self.available.notify_one();
+        Ok(())
     }
     pub fn find_job<F>(&self, mut predicate: F) -> Result<Option<Job>> where F: FnMut(&Job) -> Result<bool> { // snapshot
         if let Some(job) = lock(&self.queue, |queue| -> Result<Option<Job>> {
@@ -444,11 +855,14 @@ impl Worker {
         }
         self.db.find_job(predicate)
     }
-    pub fn find_jobs<F>(&self, mut predicate: F) -> Result<Vec<Job>> where F: FnMut(&Job) -> Result<bool> {
+    // the queue is small, so only the database goes through the index
+    pub fn find_jobs_in<F>(&self, index: JobIndex, mut predicate: F) -> Result<Vec<Job>> where F: FnMut(&Job) -> Result<bool> {
         let mut result = vec![];
+        let mut queued_ids = vec![];
         lock(&self.queue, |queue| -> Result<()> {
             for job in queue {
                 lock(job, |job| -> Result<()> {
+                    queued_ids.push(job.id);
                     if predicate(job)? {
                         result.push(job.clone());
                     }
@@ -457,11 +871,13 @@ impl Worker {
             }
             Ok(())
         })?;
-        result.extend(self.db.find_jobs(predicate)?);
+        // a finished job is stored before it leaves the queue, so it may be seen twice
+        result.extend(self.db.find_jobs_in(index, |job| Ok(!queued_ids.contains(&job.id) && predicate(job)?))?);
         Ok(result)
     }
     pub fn stop(&self) {
-        lock(&self.running, |v| *v = false);
+        lock(&self.queue, |_| lock(&self.running, |v| *v = false));
+        self.available.notify_all();
     }
     pub fn cancel_job(&self, id: i32) -> Result<()> {
         match self.find_job(move |job| Ok(job.id == id))? {
@@ -469,17 +885,25 @@ impl Worker {
                 if job.state != JobStatus::Queueing {
                     return Err(ERR_INVALID_STATE.clone().with_message(format!("Job {} not queueing.", id)));
                 }
-                lock(&self.queue, |queue| {
+                let canceled = lock(&self.queue, |queue| {
                     let mut index = None;
                     for (i, job) in queue.iter().enumerate() {
-                        if lock(job, |job| job.id == id) {
+                        // a judge thread may have claimed it in the meantime
+                        if lock(job, |job| job.id == id && job.state == JobStatus::Queueing) {
                             index = Some(i);
                             break;
                         }
                     }
-                    if let Some(i) = index { queue.remove(i); }
+                    index.and_then(|i| queue.remove(i))
                 });
-                Ok(())
+                match canceled {
+                    Some(job) => {
+                        // subscribers still learn about it, though the job itself is dropped
+                        self.update_job(&job, |job| job.state = JobStatus::Canceled);
+                        self.db.remove_job(id)
+                    }
+                    None => Err(ERR_INVALID_STATE.clone().with_message(format!("Job {} not queueing.", id)))
+                }
             },
             None => {
                 Err(ERR_NOT_FOUND.clone().with_message(format!("Job {} not found.", id)))
@@ -487,22 +911,13 @@ impl Worker {
         }
     }
     pub fn rerun_job(&self, id: i32) -> Result<Job> {
-        match self.db.find_job(move |job| Ok(job.id == id))? {
+        match self.db.get_job(id)? {
             Some(mut job) => {
                 if job.state != JobStatus::Finished {
                     return Err(ERR_INVALID_STATE.clone().with_message(format!("Job {} not finished.", id)));
                 }
-                job.state = JobStatus::Queueing;
-                job.result = Status::Waiting;
-                job.score = 0.0;
-                job.updated_time = Utc::now();
-                for case in &mut job.cases {
-                    case.info = "".into();
-                    case.memory = 0;
-                    case.time = 0;
-                    case.result = Status::Waiting;
-                }
-                self.push_job(Arc::new(Mutex::new(job.clone())));
+                job.reset();
+                self.push_job(Arc::new(Mutex::new(job.clone())))?;
                 Ok(job)
             },
             None => {
@@ -511,7 +926,7 @@ impl Worker {
         }
     }
     pub fn create_user(&self, name: &str) -> Result<User> {
-        if matches!(self.db.find_user_by_name(name)?, Some(_)) {
+        if self.db.find_user_by_name(name)?.is_some() {
             return Err(ERR_INVALID_ARGUMENT.clone().with_message(format!("User name '{}' already exists.", name)));
         }
         let user = User {
@@ -542,119 +957,188 @@ impl Worker {
         *v += 1;
         *v
     }
-    pub fn global_ranklist(&self, scoring_role: ScoringRule, tie_breaker: Option<TieBreaker>) -> Result<Vec<Ranking>> {
+    // within a contest, only what its ranklist counts, and a frozen ranklist must not learn of hidden solutions through the times,
+    // so nothing the freezes of the given contests hide is timed
+    fn fastest_times(&self, problem: &Problem, user_ids: &[i32], contest: Option<&Contest>, freezes: &[Contest]) -> Result<Option<Vec<u64>>> {
+        if problem.dynamic_ranking_ratio().is_none() {
+            return Ok(None);
+        }
+        let mut fastest = vec![u64::MAX; problem.cases.len()];
+        for user_id in user_ids {
+            let jobs = self.db.find_jobs_in(JobIndex::UserProblem(*user_id, problem.id), |job| Ok(
+                job.result == Status::Accepted &&
+                contest.is_none_or(|contest| {
+                    job.submission.contest_id == contest.id &&
+                    job.created_time >= contest.from &&
+                    job.created_time <= contest.to
+                }) &&
+                !hidden_by_freeze(freezes, job)
+            ))?;
+            for job in &jobs {
+                for (time, case) in fastest.iter_mut().zip(job.cases.iter().skip(1)) {
+                    *time = (*time).min(case.time);
+                }
+            }
+        }
+        Ok(Some(fastest))
+    }
+    // every submission counts here, so a frozen ranklist leaves out whatever any contest's freeze hides
+    pub fn global_ranklist(&self, scoring_role: ScoringRule, tie_breaker: Option<TieBreaker>, frozen: bool) -> Result<Vec<Ranking>> {
+        if let ScoringRule::Icpc = scoring_role {
+            return Err(ERR_INVALID_ARGUMENT.clone().with_message("ICPC scoring needs a contest to count penalty time from.".into()));
+        }
         let users = self.db.list_users()?;
+        let user_ids = users.iter().map(|user| user.id).collect::<Vec<_>>();
+        let freezes = if frozen { self.db.list_contests()? } else { vec![] };
+        let fastest = self.config.problems.iter()
+            .map(|problem| self.fastest_times(problem, &user_ids, None, &freezes))
+            .collect::<Result<Vec<_>>>()?;
         let mut helpers = vec![];
-        let mut result: Vec<Ranking> = vec![];
         for user in &users {
             let mut helper = RankHelper {
                 user: user.clone(),
                 jobs: vec![],
-                job_counts: vec![]
+                job_counts: vec![],
+                icpc: None,
+                pending: vec![]
             };
-            for problem in &self.config.problems {
-                let jobs = self.db.find_jobs(
-                    |job| Ok(job.submission.user_id == user.id && job.submission.problem_id == problem.id)
-                )?;
+            for (problem, fastest) in self.config.problems.iter().zip(&fastest) {
+                // scored in full before choosing, so the highest score is also the fastest
+                let jobs = self.db.find_jobs_in(JobIndex::UserProblem(user.id, problem.id), |job| Ok(
+                    job.state == JobStatus::Finished &&
+                    !hidden_by_freeze(&freezes, job)
+                ))?.into_iter().map(|job| job.with_dynamic_score(problem, fastest.as_ref())).collect::<Vec<_>>();
                 let job = scoring_role.choose(&jobs);
-                helper.jobs.push(job.map(|job| job.clone()));
+                helper.jobs.push(job.cloned());
                 helper.job_counts.push(jobs.len() as _);
             }
             helpers.push(helper);
         }
-        helpers.sort_by(|a, b| f32::total_cmp(&a.score(), &b.score()).reverse().then(match tie_breaker {
+        helpers.sort_by(|a, b| a.compare(b).then(match tie_breaker {
             Some(ref tie_breaker) => tie_breaker.compare(a, b),
             None => Ordering::Equal
         }));
-        for (i, helper) in helpers.iter().enumerate() {
-            if i != 0 && helper.score() == helpers[i-1].score() &&
-            match tie_breaker {
-                Some(ref tie_breaker) => tie_breaker.compare(helper, &helpers[i-1]).is_eq(),
-                None => true
-            } {
-                result.push(Ranking {
-                    user: helper.user.clone(),
-                    rank: result.last().unwrap().rank,
-                    scores: helper.jobs.iter().map(|job| match job {
-                        Some(job) => job.score,
-                        None => 0f32
-                    }).collect()
-                })
-            }
-            else {
-                result.push(Ranking {
-                    user: helper.user.clone(),
-                    rank: (i+1) as i32,
-                    scores: helper.jobs.iter().map(|job| match job {
-                        Some(job) => job.score,
-                        None => 0f32
-                    }).collect()
-                })
-            }
-        }
-        Ok(result)
+        Ok(rankings(&helpers, tie_breaker))
     }
-    pub fn contest_ranklist(&self, contest_id: i32, scoring_role: ScoringRule, tie_breaker: Option<TieBreaker>) -> Result<Vec<Ranking>> {
-        let contest = self.db.find_contest_by_id(contest_id)?;
-        if contest.is_none() {
-            return Err(ERR_NOT_FOUND.clone().with_message(format!("Contest {} not found.", contest_id)));
-        }
-        let contest = contest.unwrap();
+    // the rows of a contest ranklist, best first; a frozen one leaves out the submissions it hides
+    fn contest_helpers(
+        &self, contest: &Contest, scoring_role: ScoringRule, tie_breaker: Option<TieBreaker>, frozen: bool
+    ) -> Result<Vec<RankHelper>> {
         let users = self.db.find_users(|user| contest.user_ids.contains(&user.id))?;
+        let problems = contest.problem_ids.iter()
+            .map(|problem_id| self.config.problems.iter().find(|problem| problem.id == *problem_id).unwrap())
+            .collect::<Vec<_>>();
+        let freezes: &[Contest] = if frozen { std::slice::from_ref(contest) } else { &[] };
+        let fastest = problems.iter()
+            .map(|problem| self.fastest_times(problem, &contest.user_ids, Some(contest), freezes))
+            .collect::<Result<Vec<_>>>()?;
         let mut helpers = vec![];
-        let mut result: Vec<Ranking> = vec![];
         for user in &users {
             let mut helper = RankHelper {
                 user: user.clone(),
                 jobs: vec![],
-                job_counts: vec![]
+                job_counts: vec![],
+                icpc: None,
+                pending: vec![]
             };
-            for problem_id in &contest.problem_ids 
-            {
-                let problem = self.config.problems.iter().find(|problem| problem.id == *problem_id).unwrap();
-                let jobs = self.db.find_jobs(
-                    |job| Ok(job.submission.user_id == user.id && job.submission.problem_id == problem.id)
-                )?;
+            let mut attempts = vec![];
+            for (problem, fastest) in problems.iter().zip(&fastest) {
+                // only submissions to this contest within its window count, so practice after the end is left out
+                let jobs = self.db.find_jobs_in(JobIndex::UserProblem(user.id, problem.id), |job| Ok(
+                    job.state == JobStatus::Finished &&
+                    job.submission.contest_id == contest.id &&
+                    job.created_time >= contest.from &&
+                    job.created_time <= contest.to
+                ))?.into_iter().map(|job| job.with_dynamic_score(problem, fastest.as_ref()));
+                let (jobs, hidden): (Vec<_>, Vec<_>) = jobs.partition(|job| !frozen || !contest.hides(job));
                 let job = scoring_role.choose(&jobs);
-                helper.jobs.push(job.map(|job| job.clone()));
+                helper.jobs.push(job.cloned());
                 helper.job_counts.push(jobs.len() as _);
+                if frozen && contest.freeze_at.is_some() {
+                    helper.pending.push(hidden.len() as _);
+                }
+                attempts.push(ProblemAttempts::new(&jobs, contest.from));
+            }
+            if let ScoringRule::Icpc = scoring_role {
+                helper.icpc = Some(IcpcStanding::new(attempts));
             }
             helpers.push(helper);
         }
-        helpers.sort_by(|a, b| f32::total_cmp(&a.score(), &b.score()).reverse().then(match tie_breaker {
+        helpers.sort_by(|a, b| a.compare(b).then(match tie_breaker {
             Some(ref tie_breaker) => tie_breaker.compare(a, b),
             None => Ordering::Equal
         }));
-        for (i, helper) in helpers.iter().enumerate() {
-            if i != 0 && helper.score() == helpers[i-1].score() &&
-            match tie_breaker {
-                Some(ref tie_breaker) => tie_breaker.compare(helper, &helpers[i-1]).is_eq(),
-                None => true
-            } {
-                result.push(Ranking {
-                    user: helper.user.clone(),
-                    rank: result.last().unwrap().rank,
-                    scores: helper.jobs.iter().map(|job| match job {
-                        Some(job) => job.score,
-                        None => 0f32
-                    }).collect()
-                })
-            }
-            else {
-                result.push(Ranking {
-                    user: helper.user.clone(),
-                    rank: (i+1) as i32,
-                    scores: helper.jobs.iter().map(|job| match job {
-                        Some(job) => job.score,
-                        None => 0f32
-                    }).collect()
-                })
-            }
+        Ok(helpers)
+    }
+    pub fn contest_ranklist(
+        &self, contest_id: i32, scoring_role: ScoringRule, tie_breaker: Option<TieBreaker>, frozen: bool
+    ) -> Result<Vec<Ranking>> {
+        let contest = self.db.find_contest_by_id(contest_id)?;
+        if contest.is_none() {
+            return Err(ERR_NOT_FOUND.clone().with_message(format!("Contest {} not found.", contest_id)));
         }
-        Ok(result)
+        let helpers = self.contest_helpers(&contest.unwrap(), scoring_role, tie_breaker, frozen)?;
+        Ok(rankings(&helpers, tie_breaker))
+    }
+    // reveals the frozen submissions of one user on one problem, starting from the bottom of the ranklist,
+    // or all of them at once, and gives back the ranklist as the public sees it
+    pub fn resolve_contest(
+        &self, contest_id: i32, scoring_role: ScoringRule, tie_breaker: Option<TieBreaker>, all: bool
+    ) -> Result<Vec<Ranking>> {
+        let contest = self.db.find_contest_by_id(contest_id)?;
+        if contest.is_none() {
+            return Err(ERR_NOT_FOUND.clone().with_message(format!("Contest {} not found.", contest_id)));
+        }
+        let mut contest = contest.unwrap();
+        if contest.freeze_at.is_none() {
+            return Err(ERR_INVALID_STATE.clone().with_message(format!("Contest {} is not frozen.", contest_id)));
+        }
+        let helpers = self.contest_helpers(&contest, scoring_role, tie_breaker, true)?;
+        let pending = helpers.iter().rev().flat_map(|helper| {
+            helper.pending.iter().zip(&contest.problem_ids)
+                .filter(|(count, _)| **count > 0)
+                .map(|(_, problem_id)| (helper.user.id, *problem_id))
+        }).take(if all { usize::MAX } else { 1 }).collect::<Vec<_>>();
+        if pending.is_empty() {
+            return Err(ERR_INVALID_STATE.clone().with_message(format!("Contest {} has nothing left to resolve.", contest_id)));
+        }
+        contest.resolved.extend(pending);
+        self.db.put_contest(&contest)?;
+        let helpers = self.contest_helpers(&contest, scoring_role, tie_breaker, true)?;
+        Ok(rankings(&helpers, tie_breaker))
+    }
+    // a frozen contest keeps the submissions it hides out of the problem's numbers as well
+    pub fn problem_stats(&self, problem_id: i32, frozen: bool) -> Result<ProblemStats> {
+        if !self.config.problems.iter().any(|problem| problem.id == problem_id) {
+            return Err(ERR_NOT_FOUND.clone().with_message(format!("Problem {} not found.", problem_id)));
+        }
+        let contests = self.db.list_contests()?;
+        let jobs = self.db.find_jobs_in(JobIndex::Problem(problem_id), |job| Ok(
+            job.state == JobStatus::Finished &&
+            !(frozen && hidden_by_freeze(&contests, job))
+        ))?;
+        Ok(ProblemStats::new(problem_id, &jobs))
+    }
+    // only what the contest's users submitted within its window, and none of what a freeze hides
+    pub fn contest_stats(&self, contest_id: i32, frozen: bool) -> Result<Vec<ProblemStats>> {
+        let contest = self.db.find_contest_by_id(contest_id)?;
+        if contest.is_none() {
+            return Err(ERR_NOT_FOUND.clone().with_message(format!("Contest {} not found.", contest_id)));
+        }
+        let contest = contest.unwrap();
+        contest.problem_ids.iter().map(|problem_id| {
+            let index = JobIndex::CreatedTime(Some(contest.from), Some(contest.to));
+            let jobs = self.db.find_jobs_in(index, |job| Ok(
+                job.state == JobStatus::Finished &&
+                job.submission.problem_id == *problem_id &&
+                contest.user_ids.contains(&job.submission.user_id) &&
+                !(frozen && contest.hides(job))
+            ))?;
+            Ok(ProblemStats::new(*problem_id, &jobs))
+        }).collect()
     }
     pub fn find_problems<F>(&self, mut predicate: F) -> Vec<&Problem> where F: FnMut(&Problem) -> bool {
-        self.config.problems.iter().filter(|problem| predicate(*problem)).collect()
+        self.config.problems.iter().filter(|problem| predicate(problem)).collect()
     }
     pub fn get_contest_problems(&self, contest_id: i32) -> Result<Vec<&Problem>> {
         let contest = self.db.find_contest_by_id(contest_id)?;
@@ -666,4 +1150,74 @@ impl Worker {
     pub fn database(&self) -> &Database {
         &self.db
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AC: Status = Status::Accepted;
+    const PC: Status = Status::PartiallyCorrect;
+    const WA: Status = Status::WrongAnswer;
+
+    #[test]
+    fn sum_subtask_adds_weighted_cases() {
+        let cases = [(10.0, AC, 1.0), (30.0, PC, 0.5), (60.0, WA, 0.0)];
+        assert_eq!(score_subtask(SubtaskPolicy::Sum, true, &cases), (0.25, WA, false));
+        assert_eq!(score_subtask(SubtaskPolicy::Sum, true, &cases[..2]), (0.625, PC, false));
+        assert_eq!(score_subtask(SubtaskPolicy::Sum, true, &[(0.0, AC, 1.0), (0.0, PC, 0.5)]), (0.75, PC, false));
+        assert_eq!(score_subtask(SubtaskPolicy::Sum, true, &cases[..1]), (1.0, AC, true));
+        assert!(!fails_subtask(SubtaskPolicy::Sum, PC, 0.0));
+        assert!(fails_subtask(SubtaskPolicy::Sum, WA, 0.0));
+    }
+
+    #[test]
+    fn min_ratio_subtask_takes_worst_case() {
+        let cases = [(10.0, AC, 1.0), (30.0, PC, 0.4), (60.0, PC, 0.8)];
+        assert_eq!(score_subtask(SubtaskPolicy::MinRatio, true, &cases), (0.4, PC, false));
+        assert_eq!(score_subtask(SubtaskPolicy::MinRatio, true, &[(10.0, AC, 1.0), (30.0, WA, 0.0)]), (0.0, WA, false));
+        assert_eq!(score_subtask(SubtaskPolicy::MinRatio, true, &cases[..1]), (1.0, AC, true));
+        assert!(!fails_subtask(SubtaskPolicy::MinRatio, PC, 0.1));
+        assert!(fails_subtask(SubtaskPolicy::MinRatio, PC, 0.0));
+    }
+
+    #[test]
+    fn all_or_nothing_subtask_needs_every_case_accepted() {
+        let cases = [(10.0, AC, 1.0), (30.0, PC, 0.9)];
+        assert_eq!(score_subtask(SubtaskPolicy::AllOrNothing, true, &cases), (0.0, PC, false));
+        assert_eq!(score_subtask(SubtaskPolicy::AllOrNothing, true, &[(10.0, AC, 1.0), (30.0, WA, 0.0)]), (0.0, WA, false));
+        assert_eq!(score_subtask(SubtaskPolicy::AllOrNothing, true, &[(10.0, AC, 1.0), (30.0, AC, 1.0)]), (1.0, AC, true));
+        assert!(fails_subtask(SubtaskPolicy::AllOrNothing, PC, 0.9));
+    }
+
+    #[test]
+    fn subtask_with_failed_dependency_is_skipped() {
+        let (_, _, passed) = score_subtask(SubtaskPolicy::Sum, true, &[(10.0, AC, 1.0), (30.0, PC, 0.5)]);
+        assert!(!passed);
+        assert_eq!(score_subtask(SubtaskPolicy::AllOrNothing, passed, &[]), (0.0, Status::Skipped, false));
+        assert_eq!(score_subtask(SubtaskPolicy::Sum, passed, &[]), (0.0, Status::Skipped, false));
+    }
+
+    #[test]
+    fn checker_with_relative_paths_runs_isolated() {
+        // relative to the current directory, but somewhere the unprivileged sandbox user can reach
+        let depth = std::env::current_dir().unwrap().components().count() - 1;
+        let data_dir = Tempdir::new().unwrap();
+        let data = format!("{}{}", "../".repeat(depth), data_dir.path.strip_prefix("/").unwrap().display());
+        write(format!("{}/check.sh", data), "read got < \"$1\"; read expected < \"$2\"; [ \"$got\" = \"$expected\" ]\n").unwrap();
+        write(format!("{}/1.ans", data), "42\n").unwrap();
+        let tempdir = Tempdir::new().unwrap();
+        let output_file = tempdir.random();
+        write(&output_file, "42\n").unwrap();
+        let command = ["sh".into(), format!("{}/check.sh", data), output_file, format!("{}/1.ans", data)];
+        let (isolation, command) = checker_isolation(&command, &[], &tempdir.path).unwrap();
+        assert!(command[1].starts_with('/') && command[2].starts_with("/sandbox/"));
+        let sandbox = Sandbox {
+            filter: SyscallPolicy::default().compile().unwrap(),
+            cgroup_root: None,
+            isolation: Some(isolation),
+            cpu: None
+        };
+        let (result, _) = CheckerLimits::default().as_case().execute(&sandbox, &command, "/dev/null", &tempdir.random(), None);
+        assert_eq!(result, Ok(0));
+    }
+}